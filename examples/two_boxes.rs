@@ -0,0 +1,17 @@
+//! Two boxes and an arrow, built with the library and printed as ASCII.
+//!
+//! `cargo run --example two_boxes`
+
+use std::io;
+
+use dxgmr::model::{AppState, ShapeType};
+use dxgmr::renderer::write_ascii;
+
+fn main() -> io::Result<()> {
+    let mut state = AppState::new(String::from("Two boxes"));
+    let client = state.add_node(ShapeType::Box, 0, 0, "Client");
+    let server = state.add_node(ShapeType::Box, 30, 0, "Server");
+    state.connect(client, server, true);
+
+    write_ascii(&state, state.export_columns(), state.export_rows(), &mut io::stdout().lock())
+}
@@ -0,0 +1,105 @@
+//! Chained construction of diagrams in code.
+
+use crate::model::{AppState, Diagram, ShapeType};
+
+/// Builds a [`Diagram`] shape by shape. Shapes are numbered 1, 2, 3, ... in the order they're
+/// added, and those ids are what [`connect`](Self::connect) takes. Sizes and link anchors come
+/// from the same defaults the editor uses.
+///
+/// ```
+/// use dxgmr::builder::DiagramBuilder;
+/// use dxgmr::model::AppState;
+/// use dxgmr::renderer::write_ascii;
+///
+/// let diagram = DiagramBuilder::new("Login")
+///     .box_at(0, 0, "User")
+///     .box_at(30, 0, "Auth")
+///     .connect(1, 2).arrow()
+///     .build();
+///
+/// let state = AppState::from_diagram(diagram);
+/// let mut out = Vec::new();
+/// write_ascii(&state, state.export_columns(), 5, &mut out).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), concat!(
+///     "+------------------+          +------------------+\n",
+///     "|                  |          |                  |\n",
+///     "|       User       o--------->|       Auth       |\n",
+///     "|                  |          |                  |\n",
+///     "+------------------+          +------------------+\n",
+/// ));
+/// ```
+pub struct DiagramBuilder {
+    state: AppState,
+    last_connection: Option<usize>, // Index of the link the modifiers (`arrow`, `label`) apply to
+}
+
+impl DiagramBuilder {
+    pub fn new(title: &str) -> Self {
+        Self { state: AppState::new(title.to_string()), last_connection: None }
+    }
+
+    /// Adds a shape with its top-left corner at `x`, `y`, grown to fit `text`.
+    pub fn shape_at(mut self, shape: ShapeType, x: i32, y: i32, text: &str) -> Self {
+        self.state.add_node(shape, x, y, text);
+        self
+    }
+
+    pub fn box_at(self, x: i32, y: i32, text: &str) -> Self {
+        self.shape_at(ShapeType::Box, x, y, text)
+    }
+
+    pub fn diamond_at(self, x: i32, y: i32, text: &str) -> Self {
+        self.shape_at(ShapeType::Diamond, x, y, text)
+    }
+
+    pub fn text_at(self, x: i32, y: i32, text: &str) -> Self {
+        self.shape_at(ShapeType::Text, x, y, text)
+    }
+
+    /// Adds a frame of the default size; its title is `text`.
+    pub fn frame_at(self, x: i32, y: i32, text: &str) -> Self {
+        self.shape_at(ShapeType::Frame, x, y, text)
+    }
+
+    /// Links shape `from` to shape `to` with a plain line on their facing sides.
+    /// Unknown ids (or a shape linked to itself) add nothing.
+    pub fn connect(mut self, from: usize, to: usize) -> Self {
+        self.last_connection = self.state.connect(from, to, false).then(|| self.state.connections.len() - 1);
+        self
+    }
+
+    /// Puts an arrowhead at the target end of the link just added.
+    pub fn arrow(mut self) -> Self {
+        if let Some(idx) = self.last_connection {
+            self.state.connections[idx].has_arrow = true;
+        }
+        self
+    }
+
+    /// Writes `text` over the link just added, on its longest straight run.
+    ///
+    /// ```
+    /// use dxgmr::builder::DiagramBuilder;
+    /// use dxgmr::model::AppState;
+    /// use dxgmr::renderer::render_to_canvas;
+    ///
+    /// let diagram = DiagramBuilder::new("Labelled")
+    ///     .box_at(0, 0, "A")
+    ///     .box_at(30, 0, "B")
+    ///     .connect(1, 2).label("ok")
+    ///     .build();
+    /// let canvas = render_to_canvas(&AppState::from_diagram(diagram), 50, 5);
+    /// let row: String = canvas.grid[2].iter().collect();
+    /// assert_eq!(&row[19..31], "o-- ok ----o");
+    /// ```
+    pub fn label(mut self, text: &str) -> Self {
+        if let Some(idx) = self.last_connection {
+            self.state.connections[idx].label = text.replace('\n', " ");
+        }
+        self
+    }
+
+    pub fn build(self) -> Diagram {
+        self.state.to_diagram()
+    }
+}
@@ -0,0 +1,28 @@
+//! Build and render dxgmr diagrams from Rust, without the TUI.
+//!
+//! [`model::AppState`] holds a diagram's shapes and connections; [`renderer`] draws it as ASCII.
+//! [`builder::DiagramBuilder`] assembles a [`model::Diagram`] in one chained expression.
+//! The `dxgmr` binary is a terminal frontend over the same state.
+//!
+//! ```
+//! use dxgmr::model::{AppState, ShapeType};
+//! use dxgmr::renderer::write_ascii;
+//!
+//! let mut state = AppState::new(String::from("Example"));
+//! let client = state.add_node(ShapeType::Box, 0, 0, "Client");
+//! let server = state.add_node(ShapeType::Box, 30, 0, "Server");
+//! state.connect(client, server, true);
+//!
+//! let mut out = Vec::new();
+//! write_ascii(&state, state.export_columns(), state.export_rows(), &mut out).unwrap();
+//! print!("{}", String::from_utf8(out).unwrap());
+//! ```
+//!
+//! Saved `.json` files load with `serde_json` into a [`model::Diagram`], then
+//! [`model::AppState::from_diagram`].
+
+pub mod builder;
+pub mod model;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod renderer;
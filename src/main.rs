@@ -1,7 +1,7 @@
-use std::{io, time::Duration, fs};
+use std::{io::{self, Write}, time::{Duration, Instant, SystemTime}, fs};
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -11,83 +11,182 @@ use ratatui::{
     Terminal,
 };
 
-mod model;
-mod renderer;
+use dxgmr::{model, renderer};
+#[cfg(feature = "png")]
+use dxgmr::png;
 
-use crate::model::{AppState, Node, ShapeType, AppMode};
-use crate::renderer::render_to_canvas;
+use model::{AppState, Bend, Endpoint, MenuAction, MenuTarget, Node, NodeStyle, ShapeType, AppMode, Weight};
+use renderer::{render_to_canvas, write_ascii};
+
+/// Printed when the command line can't be read.
+const USAGE: &str = "Usage: dxgmr [--width <columns> | --classic] [--tab <title>]... [--watch] [--leader-timeout <ms>] [--author <name>] [new|open] <title>
+       dxgmr check <title>
+       dxgmr [--width <columns> | --classic] export [--format txt|png] <title>";
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    
-    let state = if args.len() > 1 {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // Export width and tab flags may appear anywhere; pull them out before reading the command
+    let mut export_width = None;
+    let mut tab_titles = Vec::new();
+    let mut watch = false;
+    let mut leader_timeout_ms = 0;
+    let mut author = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--classic" => {
+                export_width = Some(model::CLASSIC_EXPORT_WIDTH);
+                args.remove(i);
+            }
+            "--width" => match args.get(i + 1).and_then(|w| w.parse::<u16>().ok()).filter(|&w| w > 0) {
+                Some(width) => {
+                    export_width = Some(width);
+                    args.drain(i..i + 2);
+                }
+                None => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+            },
+            "--watch" => {
+                watch = true;
+                args.remove(i);
+            }
+            "--leader-timeout" => match args.get(i + 1).and_then(|ms| ms.parse::<u64>().ok()) {
+                Some(ms) => {
+                    leader_timeout_ms = ms;
+                    args.drain(i..i + 2);
+                }
+                None => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+            },
+            "--author" => match args.get(i + 1) {
+                Some(name) => {
+                    author = Some(name.clone());
+                    args.drain(i..i + 2);
+                }
+                None => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+            },
+            "--tab" => match args.get(i + 1) {
+                Some(title) => {
+                    tab_titles.push(title.clone());
+                    args.drain(i..i + 2);
+                }
+                None => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+            },
+            _ => i += 1,
+        }
+    }
+
+    let mut state = if args.len() > 1 {
         let cmd = &args[1];
         match cmd.as_str() {
             "new" => {
-                let title = if args.len() > 2 { args[2..].join(" ") } else { "Untitled Diagram".to_string() };
-                AppState::new(title)
+                AppState::new(args[2..].join(" "))
+            }
+            "check" => {
+                if args.len() <= 2 {
+                    println!("Usage: dxgmr check <title>");
+                    return Ok(());
+                }
+                std::process::exit(if check(&args[2..].join(" ")) { 0 } else { 1 });
+            }
+            "export" => {
+                let mut rest = args[2..].to_vec();
+                let format = match rest.iter().position(|a| a == "--format") {
+                    Some(at) if at + 1 < rest.len() => rest.drain(at..at + 2).nth(1).unwrap_or_default(),
+                    Some(_) => String::new(),
+                    None => String::from("txt"),
+                };
+                if rest.is_empty() || !matches!(format.as_str(), "txt" | "png") {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+                std::process::exit(if export(&rest.join(" "), &format, export_width) { 0 } else { 1 });
             }
             "open" => {
                 let title = if args.len() > 2 { args[2..].join(" ") } else { 
                     println!("Usage: dxgmr open <title>");
                     return Ok(());
                 };
-                let filename = format!("{}.json", title);
-                match fs::read_to_string(&filename) {
-                    Ok(data) => match serde_json::from_str(&data) {
-                        Ok(diagram) => AppState::from_diagram(diagram),
-                        Err(_) => {
-                            println!("Error: Failed to parse {}. Starting new instead.", filename);
-                            AppState::new(title)
-                        }
-                    },
-                    Err(_) => {
+                let filename = format!("{}.json", model::sanitize_filename(&title));
+                match load_diagram(&filename) {
+                    Ok(diagram) => opened(diagram, &title),
+                    Err(LoadError::NotFound) => {
                         println!("Error: File {} not found. Starting new instead.", filename);
                         AppState::new(title)
                     }
+                    Err(err) => match recover_from_load_error(&filename, title, err)? {
+                        Some(state) => state,
+                        None => return Ok(()),
+                    },
                 }
             }
-            _ => {
-                let title = args[1..].join(" ");
-                let filename = format!("{}.json", title);
-                if let Ok(data) = fs::read_to_string(&filename) {
-                    if let Ok(diagram) = serde_json::from_str(&data) {
-                        AppState::from_diagram(diagram)
-                    } else {
-                        AppState::new(title)
-                    }
-                } else {
-                    AppState::new(title)
-                }
-            }
+            _ => match open_or_new(args[1..].join(" "))? {
+                Some(state) => state,
+                None => return Ok(()),
+            },
         }
     } else {
         println!("Enter a title for your diagram:");
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
-        let title = input.trim().to_string();
-        if title.is_empty() {
-            AppState::new("Untitled Diagram".to_string())
-        } else {
-            AppState::new(title)
-        }
+        AppState::new(input.trim().to_string())
     };
+
+    state.export_width = export_width;
+    let fixes = state.repair();
+    if !fixes.is_empty() { state.edit(); }
+    let status_msg = if fixes.is_empty() {
+        String::from("Press <Space> for commands")
+    } else {
+        format!("Repaired {} issue(s) in loaded diagram", fixes.len())
+    };
+
+    // Further diagrams from --tab open in tabs behind the first
+    let mut docs = vec![state];
+    for title in tab_titles {
+        let Some(mut doc) = open_or_new(title)? else { return Ok(()) };
+        doc.export_width = export_width;
+        if !doc.repair().is_empty() { doc.edit(); }
+        docs.push(doc);
+    }
+    for doc in &mut docs {
+        doc.watch_file = watch;
+        doc.leader_timeout_ms = leader_timeout_ms;
+        doc.disk_mtime = json_mtime(doc);
+        // Only fills in a missing author; files keep whoever is already credited
+        if doc.author.is_none() {
+            doc.author = author.clone();
+        }
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // Run app
-    let res = run_app(&mut terminal, state);
+    let res = run_app(&mut terminal, docs, status_msg);
 
     // Restore terminal
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        DisableBracketedPaste
     )?;
     terminal.show_cursor()?;
 
@@ -98,26 +197,545 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::Result<()> {
-    let mut status_msg = String::from("Press <Space> for commands");
-    
+/// Files larger than this are refused rather than read into memory on open.
+const MAX_OPEN_BYTES: u64 = 16 * 1024 * 1024;
+
+enum LoadError {
+    NotFound,
+    TooLarge(u64),
+    Unreadable(io::Error),
+    NotUtf8,
+    Invalid(serde_json::Error),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotFound => write!(f, "does not exist"),
+            LoadError::TooLarge(bytes) => write!(f, "is {} KB, over the {} KB limit", bytes / 1024, MAX_OPEN_BYTES / 1024),
+            LoadError::Unreadable(err) => write!(f, "could not be read: {}", err),
+            LoadError::NotUtf8 => write!(f, "is not valid UTF-8"),
+            LoadError::Invalid(err) => write!(f, "could not be parsed: {}", err),
+        }
+    }
+}
+
+fn load_diagram(filename: &str) -> Result<model::Diagram, LoadError> {
+    let meta = fs::metadata(filename).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => LoadError::NotFound,
+        _ => LoadError::Unreadable(err),
+    })?;
+    if meta.len() > MAX_OPEN_BYTES {
+        return Err(LoadError::TooLarge(meta.len()));
+    }
+    let bytes = fs::read(filename).map_err(LoadError::Unreadable)?;
+    let data = String::from_utf8(bytes).map_err(|_| LoadError::NotUtf8)?;
+    serde_json::from_str(&data).map_err(LoadError::Invalid)
+}
+
+/// When the diagram's `.json` was last modified, if it exists.
+fn json_mtime(state: &AppState) -> Option<SystemTime> {
+    fs::metadata(state.file_name("json")).and_then(|meta| meta.modified()).ok()
+}
+
+/// Replaces the diagram with its `.json` as it now is on disk, keeping the view.
+/// Returns the status message to show.
+fn reload(state: &mut AppState) -> String {
+    let filename = state.file_name("json");
+    state.disk_mtime = json_mtime(state);
+    match load_diagram(&filename) {
+        Ok(diagram) => {
+            state.replace_diagram(diagram);
+            let fixes = state.repair();
+            // Repairs aren't on disk yet
+            if fixes.is_empty() { state.touch(); } else { state.edit(); }
+            if fixes.is_empty() {
+                format!("Reloaded {} (changed on disk)", filename)
+            } else {
+                format!("Reloaded {} (changed on disk); repaired {} issue(s)", filename, fixes.len())
+            }
+        }
+        Err(err) => format!("{} changed on disk but {}; keeping this copy", filename, err),
+    }
+}
+
+/// A loaded diagram that keeps saving to the file it was opened as, named after `title`,
+/// even when the title stored inside has changed since.
+fn opened(diagram: model::Diagram, title: &str) -> AppState {
+    let mut state = AppState::from_diagram(diagram);
+    state.file_stem = model::sanitize_filename(title);
+    state
+}
+
+/// Opens the `.json` named after `title`, or starts a new diagram of that title if there is
+/// none. Returns `None` if the file is broken and the user chose to quit.
+fn open_or_new(title: String) -> io::Result<Option<AppState>> {
+    let filename = format!("{}.json", model::sanitize_filename(&title));
+    match load_diagram(&filename) {
+        Ok(diagram) => Ok(Some(opened(diagram, &title))),
+        Err(LoadError::NotFound) => Ok(Some(AppState::new(title))),
+        Err(err) => recover_from_load_error(&filename, title, err),
+    }
+}
+
+/// `dxgmr check`: lists what loading the `.json` named after `title` would repair, leaving
+/// the file untouched. Returns whether it loaded cleanly, for the exit status.
+fn check(title: &str) -> bool {
+    let filename = format!("{}.json", model::sanitize_filename(title));
+    let diagram = match load_diagram(&filename) {
+        Ok(diagram) => diagram,
+        Err(err) => {
+            println!("Error: {} {}.", filename, err);
+            return false;
+        }
+    };
+    let mut state = AppState::from_diagram(diagram);
+    let fixes = state.repair();
+    if fixes.is_empty() {
+        println!("{}: OK", filename);
+    } else {
+        println!("{}: {} problem(s)", filename, fixes.len());
+        for fix in &fixes { println!("  - {}", fix); }
+    }
+    for (name, value) in [("Created", &state.created), ("Modified", &state.modified), ("Author", &state.author)] {
+        if let Some(value) = value { println!("  {}: {}", name, value); }
+    }
+    fixes.is_empty()
+}
+
+/// `dxgmr export`: writes the `.txt` or `.png` of the saved diagram named after `title`
+/// without opening the editor. Returns whether the file was written, for the exit status.
+fn export(title: &str, format: &str, export_width: Option<u16>) -> bool {
+    let filename = format!("{}.json", model::sanitize_filename(title));
+    let diagram = match load_diagram(&filename) {
+        Ok(diagram) => diagram,
+        Err(err) => {
+            println!("Error: {} {}.", filename, err);
+            return false;
+        }
+    };
+    let mut state = opened(diagram, title);
+    state.repair();
+    state.export_width = export_width;
+    let out_filename = state.file_name(format);
+    let written = match format {
+        #[cfg(feature = "png")]
+        "png" => png::to_png(&state.to_diagram(), PNG_SCALE)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| fs::write(&out_filename, bytes).map_err(|err| err.to_string())),
+        #[cfg(not(feature = "png"))]
+        "png" => Err(String::from("this build has no PNG support (rebuild with --features png)")),
+        _ => fs::File::create(&out_filename)
+            .and_then(|file| {
+                let mut writer = io::BufWriter::new(file);
+                export_ascii(&state, &mut writer)?;
+                writer.flush()
+            })
+            .map_err(|err| err.to_string()),
+    };
+    match written {
+        Ok(()) => {
+            println!("Wrote {}", out_filename);
+            true
+        }
+        Err(err) => {
+            println!("Error: could not write {}: {}.", out_filename, err);
+            false
+        }
+    }
+}
+
+/// Explains why an existing file couldn't be opened and asks how to continue, so it is never
+/// silently replaced by an empty diagram on the next save. Returns `None` if the user quits.
+fn recover_from_load_error(filename: &str, title: String, err: LoadError) -> io::Result<Option<AppState>> {
+    println!("Error: {} {}.", filename, err);
+    let backup = format!("{}.bak", filename);
+    if fs::metadata(&backup).is_ok() && confirm(&format!("Open the backup {} instead?", backup))? {
+        match load_diagram(&backup) {
+            Ok(diagram) => return Ok(Some(opened(diagram, &title))),
+            Err(err) => println!("Error: {} {}.", backup, err),
+        }
+    }
+    if confirm(&format!("Start a new diagram? Saving it will overwrite {}.", filename))? {
+        Ok(Some(AppState::new(title)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    println!("{} [y/N]", question);
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+/// Streams the ASCII that Leader w/c export: the whole diagram, sized to its shapes rather than
+/// the terminal or the view, or in selection mode just the selected shapes cropped to fit.
+/// Falls back to the whole diagram when nothing is selected.
+fn export_ascii<W: Write>(state: &AppState, writer: &mut W) -> io::Result<()> {
+    match state.selection_subset().filter(|_| state.export_selection) {
+        Some(subset) => write_ascii(&subset, subset.export_columns(), subset.export_rows(), writer),
+        None => write_ascii(state, state.export_columns(), state.export_rows(), writer),
+    }
+}
+
+/// `export_ascii` collected into a string, for the clipboard.
+fn export_text(state: &AppState) -> String {
+    let mut out = Vec::new();
+    let _ = export_ascii(state, &mut out);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+/// Writes the diagram's `.txt` and `.json` (keeping the previous `.json` as `.json.bak`) and
+/// returns the status message to show.
+fn save(state: &mut AppState) -> String {
+    // Save ASCII .txt, streamed straight to the file
+    let txt_filename = state.file_name("txt");
+    if let Ok(file) = fs::File::create(&txt_filename) {
+        let mut writer = io::BufWriter::new(file);
+        let _ = export_ascii(state, &mut writer).and_then(|_| writer.flush());
+    }
+
+    // Save Model .json
+    state.stamp_save(SystemTime::now());
+    let diagram = state.to_diagram();
+    let json_filename = state.file_name("json");
+    let Ok(json) = serde_json::to_string_pretty(&diagram) else {
+        return format!("Failed to encode {}", json_filename);
+    };
+
+    // Keep one previous version around; a failed rename shouldn't block the save
+    let backup_filename = format!("{}.bak", json_filename);
+    let backup_note = match fs::rename(&json_filename, &backup_filename) {
+        Ok(()) => format!(" (previous version in {})", backup_filename),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(_) => String::from(" (backup failed)"),
+    };
+
+    if fs::write(&json_filename, json).is_ok() {
+        state.mark_saved();
+        // Our own write isn't an outside change to reload
+        state.disk_mtime = json_mtime(state);
+        format!("Saved {} and {}!{}", txt_filename, json_filename, backup_note)
+    } else {
+        format!("Failed to write {}{}", json_filename, backup_note)
+    }
+}
+
+/// Screen pixels per font pixel in `dxgmr export --format png`.
+#[cfg(feature = "png")]
+const PNG_SCALE: u32 = 2;
+
+/// Status shown when resizing a Text shape, which always fits its label.
+const TEXT_RESIZE_MSG: &str = "Text shapes fit their text: edit it (i) to resize, or x to convert it to a Box";
+
+/// Status line while a connection label is being typed.
+fn label_prompt(label: &str) -> String {
+    format!("Label: {}_ (Enter or Esc to finish, Backspace to delete)", label)
+}
+
+/// Status line while typing at the geometry prompt.
+fn geometry_prompt(input: &str) -> String {
+    format!("Place: {}_ (x= y= w= h=; Enter applies, Esc cancels)", input)
+}
+
+/// Percent of a link's span that `[` / `]` slide its bend by.
+const BEND_STEP: i8 = 10;
+
+/// Columns taken by the right-click menu popup, borders included.
+const CONTEXT_MENU_WIDTH: u16 = 21;
+
+/// Runs a context-menu entry against whatever was right-clicked at `world`.
+/// Returns the status message to show, if any.
+fn apply_menu_action(state: &mut AppState, action: MenuAction, target: MenuTarget, world: model::Point) -> Option<String> {
+    state.mode = AppMode::Normal;
+    state.touch();
+    let locked = match target {
+        MenuTarget::Node(id) => state.nodes.iter().any(|n| n.id == id && n.locked),
+        _ => false,
+    };
+    match (action, target) {
+        (MenuAction::NewShape(shape), _) => {
+            let id = state.next_id();
+            let (width, height) = shape.default_size();
+            let (x, y) = state.free_spot(shape, world.0, world.1, width, height);
+            let text = state.templates.get(&shape).cloned().unwrap_or_default();
+            state.template_pending = (!text.is_empty()).then_some(id);
+            for n in &mut state.nodes { n.selected = false; }
+            state.nodes.push(Node { id, shape, x, y, width, height, text, selected: true, locked: false, group: None, style: NodeStyle::Plain, shadow: false });
+            if let Some(node) = state.nodes.last_mut() { node.fit_to_text(); }
+            state.edit();
+            state.selected_connection_index = None;
+            state.mode = AppMode::Insert(id);
+            None
+        }
+        (MenuAction::EditText | MenuAction::Resize | MenuAction::Delete, MenuTarget::Node(_)) if locked => {
+            Some(String::from("Shape is locked"))
+        }
+        (MenuAction::EditText, MenuTarget::Node(id)) => {
+            for n in &mut state.nodes { n.selected = n.id == id; }
+            state.selected_connection_index = None;
+            state.mode = AppMode::Insert(id);
+            None
+        }
+        (MenuAction::Resize, MenuTarget::Node(id)) => {
+            for n in &mut state.nodes { n.selected = n.id == id; }
+            state.selected_connection_index = None;
+            let node = state.nodes.iter().find(|n| n.id == id)?;
+            if !node.shape.resizable() {
+                return Some(String::from(TEXT_RESIZE_MSG));
+            }
+            state.mode = AppMode::Resize(id);
+            Some(format!("Resize Mode ({}x{}): +/- to scale, arrows for width/height, Esc to finish", node.width, node.height))
+        }
+        (MenuAction::Duplicate, MenuTarget::Node(id)) => {
+            let mut copy = state.nodes.iter().find(|n| n.id == id)?.clone();
+            copy.id = state.next_id();
+            (copy.x, copy.y) = state.free_spot(copy.shape, copy.x + 2, copy.y + 1, copy.width, copy.height);
+            copy.locked = false;
+            copy.group = None;
+            copy.selected = true;
+            for n in &mut state.nodes { n.selected = false; }
+            state.nodes.push(copy);
+            state.selected_connection_index = None;
+            state.edit();
+            Some(String::from("Shape duplicated"))
+        }
+        (MenuAction::ToggleLock, MenuTarget::Node(id)) => {
+            let node = state.nodes.iter_mut().find(|n| n.id == id)?;
+            node.locked = !node.locked;
+            let locked = node.locked;
+            state.edit();
+            Some(if locked { String::from("Shape locked") } else { String::from("Shape unlocked") })
+        }
+        (MenuAction::Connect { arrow }, MenuTarget::Node(id)) => state.start_connection(id, arrow),
+        (MenuAction::Delete, MenuTarget::Node(id)) => {
+            state.nodes.retain(|n| n.id != id);
+            state.connections.retain(|c| !c.touches(id));
+            state.edit();
+            Some(String::from("Shape and connections deleted"))
+        }
+        (MenuAction::ToggleArrow, MenuTarget::Connection(idx)) => {
+            let msg = String::from(state.connections.get_mut(idx)?.cycle_arrows());
+            state.edit();
+            Some(msg)
+        }
+        (MenuAction::ToggleWeight, MenuTarget::Connection(idx)) => {
+            let conn = state.connections.get_mut(idx)?;
+            conn.weight = conn.weight.next();
+            let msg = format!("Connection weight: {:?}", conn.weight);
+            state.edit();
+            Some(msg)
+        }
+        (MenuAction::Reverse, MenuTarget::Connection(idx)) => {
+            if !state.connections.get_mut(idx)?.reverse() {
+                return Some(String::from("Can't reverse a link to a free point"));
+            }
+            state.edit();
+            Some(String::from("Connection reversed"))
+        }
+        (MenuAction::EditLabel, MenuTarget::Connection(idx)) => {
+            let conn = state.connections.get(idx)?;
+            for n in &mut state.nodes { n.selected = false; }
+            state.selected_connection_index = Some(idx);
+            state.mode = AppMode::Label(idx);
+            Some(label_prompt(&conn.label))
+        }
+        (MenuAction::Delete, MenuTarget::Connection(idx)) if idx < state.connections.len() => {
+            state.connections.remove(idx);
+            state.selected_connection_index = None;
+            state.edit();
+            Some(String::from("Connection deleted"))
+        }
+        _ => None,
+    }
+}
+
+/// The second pane of a split view: which diagram it shows and from where. Input always goes to
+/// `docs[active]`, drawn in the right-hand pane when `focus_right` and the left one otherwise.
+#[derive(Clone, Copy)]
+struct Split {
+    other: usize,
+    camera: (i32, i32),
+    focus_right: bool,
+}
+
+/// Shape palette rows: the shape, a preview glyph, its name and its Leader key.
+const PALETTE: [(ShapeType, &str, &str, char); 4] = [
+    (ShapeType::Box, "+-+", "Box", 'n'),
+    (ShapeType::Diamond, "/\\", "Diamond", 'd'),
+    (ShapeType::Text, "Aa", "Text", 't'),
+    (ShapeType::Frame, "[ ]", "Frame", 'f'),
+];
+
+/// Columns the palette takes in the margin left of the canvas, borders included.
+const PALETTE_WIDTH: u16 = 18;
+
+/// What a pane's cached canvas text was drawn from: tab, state version, camera and size.
+type CanvasKey = (usize, u64, (i32, i32), u16, u16);
+
+/// Terminal cell showing world point `(x, y)` in a canvas pane at `area` scrolled to `camera`,
+/// if it is on screen.
+fn screen_cell(area: ratatui::layout::Rect, camera: (i32, i32), (x, y): model::Point) -> Option<(u16, u16)> {
+    let (col, row) = (x - camera.0, y - camera.1);
+    (col >= 0 && row >= 0 && col < area.width as i32 && row < area.height as i32)
+        .then(|| (area.x + col as u16, area.y + row as u16))
+}
+
+/// Hands the focus to the other pane of a split, keeping each pane's viewport where it was.
+fn switch_focus(docs: &mut [AppState], active: &mut usize, split: &mut Split) -> String {
+    docs[*active].mode = AppMode::Normal;
+    docs[*active].touch();
+    let camera = docs[*active].camera_offset;
+    docs[split.other].camera_offset = split.camera;
+    (split.other, *active) = (*active, split.other);
+    split.camera = camera;
+    split.focus_right = !split.focus_right;
+    format!("Focus: {}", docs[*active].display_title())
+}
+
+/// Moves to the next tab (or the previous one when `back`) and returns the status message.
+fn switch_tab(docs: &mut [AppState], active: &mut usize, back: bool) -> String {
+    docs[*active].mode = AppMode::Normal;
+    docs[*active].touch();
+    if docs.len() < 2 {
+        return String::from("Only one diagram open (add more with --tab <title>)");
+    }
+    *active = if back { (*active + docs.len() - 1) % docs.len() } else { (*active + 1) % docs.len() };
+    format!("Switched to {}", docs[*active].display_title())
+}
+
+/// Moves quitting on to the first diagram from `from` on with unsaved changes: switches to its
+/// tab and asks whether to save it. Returns false once none are left, so the app can exit.
+fn ask_next_unsaved(docs: &mut [AppState], from: usize, active: &mut usize, status_msg: &mut String) -> bool {
+    let Some(i) = next_dirty(docs, from) else { return false };
+    docs[*active].mode = AppMode::Normal;
+    docs[*active].touch();
+    *active = i;
+    docs[i].mode = AppMode::ConfirmQuit;
+    docs[i].touch();
+    *status_msg = format!("{} has unsaved changes. Save it before quitting? (y/n, Esc cancels)", docs[i].display_title());
+    true
+}
+
+/// How often watched `.json` files are checked for changes.
+const WATCH_POLL_MS: u128 = 500;
+
+/// Index of the first open diagram at or after `from` with unsaved changes.
+fn next_dirty(docs: &[AppState], from: usize) -> Option<usize> {
+    (from..docs.len()).find(|&i| docs[i].is_dirty())
+}
+
+/// Input and drawing go to `docs[active]`; the other diagrams wait in their tabs.
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut docs: Vec<AppState>, mut status_msg: String) -> io::Result<()> {
+    let mut active = 0;
+    // Whose node sizes `sizes` holds, so switching tabs doesn't reanchor against the wrong diagram
+    let mut sized = active;
+    let mut sizes = docs[active].node_sizes();
+    // Last rendered canvas text
+    let mut canvas_cache: Option<(CanvasKey, String)> = None;
+    // Side-by-side view, and the last text drawn in its unfocused pane
+    let mut split: Option<Split> = None;
+    let mut other_cache: Option<(CanvasKey, String)> = None;
+    let mut last_watch = Instant::now();
+    let mut show_palette = false;
+    // Shape picked in the palette, placed by the next click on the canvas
+    let mut palette_armed: Option<ShapeType> = None;
     loop {
+        // Pick up watched .json files changed by other programs, unless that would lose edits
+        if last_watch.elapsed().as_millis() >= WATCH_POLL_MS {
+            last_watch = Instant::now();
+            for (i, doc) in docs.iter_mut().enumerate() {
+                if !doc.watch_file || doc.mode != AppMode::Normal { continue; }
+                let mtime = json_mtime(doc);
+                if mtime.is_none() || mtime == doc.disk_mtime { continue; }
+                if !doc.is_dirty() {
+                    status_msg = reload(doc);
+                } else if i == active {
+                    doc.mode = AppMode::ConfirmReload;
+                    doc.touch();
+                    status_msg = format!("{} changed on disk. Reload it and lose your changes? (y/n)", doc.file_name("json"));
+                }
+            }
+        }
+        // The tab bar only shows with several diagrams open
+        let tabs: Vec<(String, bool)> = if docs.len() > 1 {
+            docs.iter().map(|doc| (doc.display_title().to_string(), doc.is_dirty())).collect()
+        } else {
+            Vec::new()
+        };
+        let state = &mut docs[active];
+        if sized != active {
+            sizes = state.node_sizes();
+            sized = active;
+        }
+        state.log_status(&status_msg);
+        // However the last event resized a node, its links stay on the border
+        state.reanchor_resized(&sizes);
+        sizes = state.node_sizes();
+        state.track_selection();
+        // Leader gives up after a spell without keys, in case Space was hit by accident
+        if state.mode == AppMode::Leader && state.leader_timeout_ms > 0 {
+            let since = *state.leader_since.get_or_insert_with(Instant::now);
+            if since.elapsed().as_millis() >= state.leader_timeout_ms as u128 {
+                state.mode = AppMode::Normal;
+                state.touch();
+                status_msg = String::from("Leader timed out (no key pressed)");
+            }
+        }
+        if state.mode != AppMode::Leader {
+            state.leader_since = None;
+        }
+        state.refresh_index();
         let mut inner_area_cache = ratatui::layout::Rect::default();
+        let mut other_area_cache = ratatui::layout::Rect::default();
+        let mut palette_area_cache = ratatui::layout::Rect::default();
         let mut cursor_pos: Option<(u16, u16)> = None;
         let size = terminal.size()?;
         let area = ratatui::layout::Rect::new(0, 0, size.width, size.height);
 
+        // Drawing only reads the diagrams, so a split can show any of them next to the active one
+        let state = &docs[active];
         terminal.draw(|f| {
+            // One 79-wide column, or two side by side when split
+            let panes = if split.is_some() { 2 } else { 1 };
+            let pane_width = 79.min(area.width / panes);
             let horizontal_chunks = ratatui::layout::Layout::default()
                 .direction(ratatui::layout::Direction::Horizontal)
                 .constraints([
-                    ratatui::layout::Constraint::Length(area.width.saturating_sub(79) / 2),
-                    ratatui::layout::Constraint::Length(79.min(area.width)),
+                    ratatui::layout::Constraint::Length(area.width.saturating_sub(pane_width * panes) / 2),
+                    ratatui::layout::Constraint::Length(pane_width * panes),
                     ratatui::layout::Constraint::Min(0),
                 ])
                 .split(area);
             let display_area = horizontal_chunks[1];
 
+            // PALETTE, in the left margin when there's room for it
+            let margin = horizontal_chunks[0];
+            if show_palette && margin.width >= PALETTE_WIDTH {
+                let rows: Vec<ratatui::text::Line> = PALETTE.iter().map(|&(shape, glyph, name, key)| {
+                    let line = format!(" {:<3} {:<8} \u{2423}{}", glyph, name, key);
+                    if palette_armed == Some(shape) {
+                        ratatui::text::Line::styled(line, ratatui::style::Style::default().add_modifier(ratatui::style::Modifier::REVERSED))
+                    } else {
+                        ratatui::text::Line::from(line)
+                    }
+                }).collect();
+                let palette_area = ratatui::layout::Rect {
+                    x: margin.x + margin.width - PALETTE_WIDTH,
+                    width: PALETTE_WIDTH,
+                    height: (rows.len() as u16 + 2).min(margin.height),
+                    ..margin
+                };
+                let block = Block::default()
+                    .title(" Shapes ")
+                    .borders(Borders::ALL)
+                    .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray));
+                palette_area_cache = block.inner(palette_area);
+                f.render_widget(Paragraph::new(rows).block(block), palette_area);
+            }
+
             let chunks = ratatui::layout::Layout::default()
                 .direction(ratatui::layout::Direction::Vertical)
                 .constraints([
@@ -126,51 +744,119 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                 ])
                 .split(display_area);
 
-            let main_area = chunks[0];
+            let mut main_area = chunks[0];
             let status_bar_area = chunks[1];
 
+            // TAB BAR
+            if !tabs.is_empty() {
+                let tab_bar_area = ratatui::layout::Rect { height: 1, ..main_area };
+                main_area.y += 1;
+                main_area.height = main_area.height.saturating_sub(1);
+                let spans: Vec<ratatui::text::Span> = tabs.iter().enumerate().map(|(i, (title, dirty))| {
+                    let label = format!(" {}{} ", title, if *dirty { "*" } else { "" });
+                    if i == active {
+                        ratatui::text::Span::styled(label, ratatui::style::Style::default().bg(ratatui::style::Color::Blue).fg(ratatui::style::Color::Black).add_modifier(ratatui::style::Modifier::BOLD))
+                    } else {
+                        ratatui::text::Span::raw(label)
+                    }
+                }).collect();
+                f.render_widget(Paragraph::new(ratatui::text::Line::from(spans)).style(ratatui::style::Style::default().bg(ratatui::style::Color::Indexed(235))), tab_bar_area);
+            }
+
+            // SPLIT: the unfocused pane, dimmed
+            if let Some(split) = split {
+                let left = ratatui::layout::Rect { width: pane_width, ..main_area };
+                let right = ratatui::layout::Rect { x: main_area.x + pane_width, width: pane_width, ..main_area };
+                let (focused, unfocused) = if split.focus_right { (right, left) } else { (left, right) };
+                main_area = focused;
+                let other = &docs[split.other];
+                let block = Block::default()
+                    .title(format!(" {} ", other.display_title()))
+                    .borders(Borders::ALL)
+                    .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray));
+                other_area_cache = block.inner(unfocused);
+                f.render_widget(block, unfocused);
+                let key = (split.other, other.version, split.camera, other_area_cache.width, other_area_cache.height);
+                if other_cache.as_ref().is_none_or(|(cached, _)| *cached != key) {
+                    let canvas = renderer::render_region(other, split.camera, other_area_cache.width, other_area_cache.height);
+                    other_cache = Some((key, canvas.to_string()));
+                }
+                if let Some((_, text)) = &other_cache {
+                    f.render_widget(Paragraph::new(text.as_str()).style(ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray)), other_area_cache);
+                }
+            }
+
             // MAIN CANVAS
             let block = Block::default()
-                .title(format!(" {} ", state.title))
+                .title(format!(" {} ", state.display_title()))
                 .borders(Borders::ALL)
                 .border_style(match state.mode {
                     AppMode::Normal => ratatui::style::Style::default().fg(ratatui::style::Color::Blue),
                     AppMode::Insert(_) => ratatui::style::Style::default().fg(ratatui::style::Color::Green),
+                    AppMode::Label(_) => ratatui::style::Style::default().fg(ratatui::style::Color::Green),
+                    AppMode::Geometry(_) => ratatui::style::Style::default().fg(ratatui::style::Color::Magenta),
                     AppMode::Leader => ratatui::style::Style::default().fg(ratatui::style::Color::Yellow),
                     AppMode::Resize(_) => ratatui::style::Style::default().fg(ratatui::style::Color::Magenta),
                     AppMode::Help => ratatui::style::Style::default().fg(ratatui::style::Color::Cyan),
+                    AppMode::Stats => ratatui::style::Style::default().fg(ratatui::style::Color::Cyan),
+                    AppMode::Log(_) => ratatui::style::Style::default().fg(ratatui::style::Color::Cyan),
+                    AppMode::QuickConnect { .. } => ratatui::style::Style::default().fg(ratatui::style::Color::Yellow),
                     AppMode::ContextMenu { .. } => ratatui::style::Style::default().fg(ratatui::style::Color::White),
+                    AppMode::ConfirmQuit | AppMode::ConfirmReload => ratatui::style::Style::default().fg(ratatui::style::Color::Red),
                 });
             inner_area_cache = block.inner(main_area);
             f.render_widget(block, main_area);
 
-            let canvas = render_to_canvas(&state, inner_area_cache.width, inner_area_cache.height);
-            f.render_widget(Paragraph::new(canvas.to_string()), inner_area_cache);
+            // Nothing visible changes between input events, so idle frames reuse the last canvas
+            let cache_key = (active, state.version, state.camera_offset, inner_area_cache.width, inner_area_cache.height);
+            if canvas_cache.as_ref().is_none_or(|(key, _)| *key != cache_key) {
+                let mut canvas = render_to_canvas(state, inner_area_cache.width, inner_area_cache.height);
+                // Drawn here rather than in render_to_canvas so exports never pick it up
+                if state.show_minimap {
+                    canvas.draw_minimap(state);
+                }
+                canvas_cache = Some((cache_key, canvas.to_string()));
+            }
+            if let Some((_, text)) = &canvas_cache {
+                f.render_widget(Paragraph::new(text.as_str()), inner_area_cache);
+            }
 
             // STATUS BAR
             let (mode_text, mode_color) = match state.mode {
                 AppMode::Normal => (" NORMAL ", ratatui::style::Color::Blue),
                 AppMode::Insert(_) => (" INSERT ", ratatui::style::Color::Green),
+                AppMode::Label(_) => (" LABEL ", ratatui::style::Color::Green),
+                AppMode::Geometry(_) => (" PLACE ", ratatui::style::Color::Magenta),
                 AppMode::Leader => (" LEADER ", ratatui::style::Color::Yellow),
                 AppMode::Resize(_) => (" RESIZE ", ratatui::style::Color::Magenta),
                 AppMode::Help => (" HELP ", ratatui::style::Color::Cyan),
+                AppMode::Stats => (" STATS ", ratatui::style::Color::Cyan),
+                AppMode::Log(_) => (" LOG ", ratatui::style::Color::Cyan),
+                AppMode::QuickConnect { .. } => (" LINK # ", ratatui::style::Color::Yellow),
                 AppMode::ContextMenu { .. } => (" MENU ", ratatui::style::Color::White),
+                AppMode::ConfirmQuit => (" QUIT? ", ratatui::style::Color::Red),
+                AppMode::ConfirmReload => (" RELOAD? ", ratatui::style::Color::Red),
             };
 
             let status_bar = Paragraph::new(ratatui::text::Line::from(vec![
                 ratatui::text::Span::styled(mode_text, ratatui::style::Style::default().bg(mode_color).fg(ratatui::style::Color::Black).add_modifier(ratatui::style::Modifier::BOLD)),
+                ratatui::text::Span::styled(
+                    state.leader_since.map(|since| {
+                        let left = Duration::from_millis(state.leader_timeout_ms).saturating_sub(since.elapsed());
+                        format!(" {:.1}s", left.as_secs_f32())
+                    }).unwrap_or_default(),
+                    ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray),
+                ),
+                ratatui::text::Span::styled(
+                    state.pending_source_name().map(|name| format!(" [linking from {}]", name)).unwrap_or_default(),
+                    ratatui::style::Style::default().fg(ratatui::style::Color::Yellow).add_modifier(ratatui::style::Modifier::BOLD),
+                ),
                 ratatui::text::Span::raw(format!(" | {}", status_msg)),
             ])).style(ratatui::style::Style::default().bg(ratatui::style::Color::Indexed(235)));
             f.render_widget(status_bar, status_bar_area);
 
             // LEADER MENU (POPUP)
             if state.mode == AppMode::Leader {
-                let popup_area = ratatui::layout::Rect {
-                    x: area.width / 2 - 15,
-                    y: area.height / 2 - 5,
-                    width: 30,
-                    height: 9,
-                };
                 let menu_block = Block::default()
                     .title(" Commands ")
                     .borders(Borders::ALL)
@@ -181,13 +867,34 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                     ratatui::text::Line::from("  d -> New Diamond"),
                     ratatui::text::Line::from("  t -> New Text"),
                     ratatui::text::Line::from("  f -> New Frame"),
-                    ratatui::text::Line::from(format!("  w -> Write ({}.txt/.json)", state.title)),
+                    ratatui::text::Line::from(format!("  w -> Write ({}.txt/.json)", state.file_stem)),
                     ratatui::text::Line::from("  c -> Copy to Clipboard"),
+                    ratatui::text::Line::from("  C -> Copy Markdown summary"),
+                    ratatui::text::Line::from("  r -> Renumber node ids 1..N"),
+                    ratatui::text::Line::from("  T -> Tidy overlapping shapes"),
+                    ratatui::text::Line::from(if state.snap_links { "  a -> Link Snapping (now: on)" } else { "  a -> Link Snapping (now: off)" }),
+                    ratatui::text::Line::from(if show_palette { "  p -> Shape Palette (now: on)" } else { "  p -> Shape Palette (now: off)" }),
+                    ratatui::text::Line::from(if state.legend.visible { "  L -> Legend (now: on)" } else { "  L -> Legend (now: off)" }),
+                    ratatui::text::Line::from("  K -> Legend Corner"),
+                    ratatui::text::Line::from(if state.export_selection { "  s -> Export All (now: sel.)" } else { "  s -> Export Selection Only" }),
+                    ratatui::text::Line::from("  i -> Diagram Info"),
+                    ratatui::text::Line::from("  m -> Message Log"),
+                    ratatui::text::Line::from("  l -> Link by Clicking"),
+                    ratatui::text::Line::from("  Tab -> Next Open Diagram"),
+                    ratatui::text::Line::from(if split.is_some() { "  v -> Close Split View" } else { "  v -> Split View" }),
+                    ratatui::text::Line::from("  o -> Focus Other Pane"),
                     ratatui::text::Line::from("  h -> Help Menu"),
                     ratatui::text::Line::from("  q -> Quit"),
                     ratatui::text::Line::from(""),
                     ratatui::text::Line::from("  <Esc> -> Cancel"),
                 ];
+                let height = menu_text.len() as u16 + 2;
+                let popup_area = ratatui::layout::Rect {
+                    x: area.width.saturating_sub(30) / 2,
+                    y: (area.height / 2).saturating_sub(height / 2),
+                    width: 30.min(area.width),
+                    height: height.min(area.height),
+                };
                 let menu = Paragraph::new(menu_text).block(menu_block);
                 f.render_widget(ratatui::widgets::Clear, popup_area);
                 f.render_widget(menu, popup_area);
@@ -195,12 +902,6 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
 
             // HELP MENU (POPUP)
             if state.mode == AppMode::Help {
-                let popup_area = ratatui::layout::Rect {
-                    x: area.width / 2 - 25,
-                    y: area.height / 2 - 12,
-                    width: 50,
-                    height: 24,
-                };
                 let help_block = Block::default()
                     .title(" Full Command Reference ")
                     .borders(Borders::ALL)
@@ -208,52 +909,149 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                 
                 let help_text = vec![
                     ratatui::text::Line::from(ratatui::text::Span::styled("--- NAVIGATION & SELECTION ---", ratatui::style::Style::default().add_modifier(ratatui::style::Modifier::BOLD))),
-                    ratatui::text::Line::from("  Tab / BackTab   : Cycle through shapes"),
+                    ratatui::text::Line::from("  Tab / BackTab   : Cycle shapes in reading order"),
                     ratatui::text::Line::from("  Arrows          : Move shape or pan canvas"),
-                    ratatui::text::Line::from("  Esc             : Clear selection / Back to Normal"),
+                    ratatui::text::Line::from("  Shift + Arrows  : Move / pan in larger steps"),
+                    ratatui::text::Line::from("  Ctrl + Arrows   : Select nearest shape that way"),
+                    ratatui::text::Line::from("  Ctrl + O        : Back to the previously selected shape"),
+                    ratatui::text::Line::from("  Alt + Arrows    : Move frame without its contents"),
+                    ratatui::text::Line::from("  Esc             : Back one step (link, conn, shapes)"),
                     ratatui::text::Line::from(""),
                     ratatui::text::Line::from(ratatui::text::Span::styled("--- EDITING ---", ratatui::style::Style::default().add_modifier(ratatui::style::Modifier::BOLD))),
-                    ratatui::text::Line::from("  i               : Enter Insert mode (Edit text)"),
-                    ratatui::text::Line::from("  r               : Enter Resize mode (+/- to scale)"),
-                    ratatui::text::Line::from("  Del / Backspace : Delete selected shape/connection"),
+                    ratatui::text::Line::from("  i               : Enter Insert mode (Edit text; on a link, its label)"),
+                    ratatui::text::Line::from("  r               : Resize mode (+/-, arrows; Text fits its text)"),
+                    ratatui::text::Line::from("  l               : Lock / unlock selected shape"),
+                    ratatui::text::Line::from("  F               : Fit selected frame to contents"),
+                    ratatui::text::Line::from("  g / G           : Group / ungroup selection"),
+                    ratatui::text::Line::from("  #               : Show / hide node id badges"),
+                    ratatui::text::Line::from("  M               : Show / hide the mini-map"),
+                    ratatui::text::Line::from("  E               : Draw connections behind / over shapes"),
+                    ratatui::text::Line::from("  Home / End      : Pan to the diagram's top-left / bottom-right"),
+                    ratatui::text::Line::from("  PgUp / PgDn     : Pan a full screen up / down"),
+                    ratatui::text::Line::from("  s               : Cycle border (plain/round/double)"),
+                    ratatui::text::Line::from("  S               : Toggle drop shadow on boxes"),
+                    ratatui::text::Line::from("  y / P           : Copy style / paste it onto the selection"),
+                    ratatui::text::Line::from("  =               : Type exact position / size (x= y= w= h=)"),
+                    ratatui::text::Line::from("  x               : Convert shape (box/diamond/...)"),
+                    ratatui::text::Line::from("  T               : Use shape's text as its template"),
+                    ratatui::text::Line::from("  Del / Backspace : Delete selection (or hovered link)"),
                     ratatui::text::Line::from(""),
                     ratatui::text::Line::from(ratatui::text::Span::styled("--- CONNECTORS ---", ratatui::style::Style::default().add_modifier(ratatui::style::Modifier::BOLD))),
                     ratatui::text::Line::from("  c               : Start plain connector from shape"),
                     ratatui::text::Line::from("  a               : Start arrow connector from shape"),
                     ratatui::text::Line::from("  Enter           : Finish connector on target shape"),
-                    ratatui::text::Line::from("  a (on conn)     : Toggle arrow on selection"),
+                    ratatui::text::Line::from("  a (on conn)     : Cycle arrows: none, end, both"),
+                    ratatui::text::Line::from("  w (on conn)     : Toggle thin / thick line"),
+                    ratatui::text::Line::from("  v (on conn)     : Reverse direction"),
+                    ratatui::text::Line::from("  b (on conn)     : Straighten, or move the bend"),
+                    ratatui::text::Line::from("  [ / ] (on conn) : Slide the bend toward source / target"),
+                    ratatui::text::Line::from("  k               : Quick connect by shape number"),
+                    ratatui::text::Line::from("  <Leader> + l    : Link by clicking source, target"),
+                    ratatui::text::Line::from("  <Leader> + a    : Toggle straightening near-aligned links"),
                     ratatui::text::Line::from(""),
                     ratatui::text::Line::from(ratatui::text::Span::styled("--- COMMANDS (<Leader> = Space) ---", ratatui::style::Style::default().add_modifier(ratatui::style::Modifier::BOLD))),
                     ratatui::text::Line::from("  <Leader> + n    : Create new Box"),
                     ratatui::text::Line::from("  <Leader> + d    : Create new Diamond"),
                     ratatui::text::Line::from("  <Leader> + t    : Create new Text"),
                     ratatui::text::Line::from("  <Leader> + f    : Create new Frame"),
+                    ratatui::text::Line::from("  <Leader> + p    : Show / hide the shape palette"),
                     ratatui::text::Line::from("  <Leader> + w    : Save (.json and .txt)"),
                     ratatui::text::Line::from("  <Leader> + c    : Copy ASCII to clipboard"),
+                    ratatui::text::Line::from("  <Leader> + C    : Copy a Markdown table of shapes and links"),
+                    ratatui::text::Line::from("  <Leader> + r    : Renumber node ids 1..N (links follow)"),
+                    ratatui::text::Line::from("  <Leader> + T    : Nudge overlapping shapes apart"),
+                    ratatui::text::Line::from("  <Leader> + s    : Toggle selection-only export"),
+                    ratatui::text::Line::from("  <Leader> + i    : Diagram info (counts, extent)"),
+                    ratatui::text::Line::from("  <Leader> + m    : Message log (Up/Down to scroll)"),
+                    ratatui::text::Line::from("  Ctrl+Tab        : Next open diagram (or <Leader> + Tab)"),
+                    ratatui::text::Line::from("  <Leader> + v    : Split view (two diagrams / views)"),
+                    ratatui::text::Line::from("  <Leader> + o    : Focus the other pane"),
                     ratatui::text::Line::from(""),
                     ratatui::text::Line::from(ratatui::text::Span::styled("  Press <Esc> or <Space> to close Help", ratatui::style::Style::default().fg(ratatui::style::Color::Yellow))),
                 ];
+                let height = help_text.len() as u16 + 2;
+                let popup_area = ratatui::layout::Rect {
+                    x: area.width.saturating_sub(50) / 2,
+                    y: (area.height / 2).saturating_sub(height / 2),
+                    width: 50.min(area.width),
+                    height: height.min(area.height),
+                };
                 let help = Paragraph::new(help_text).block(help_block);
                 f.render_widget(ratatui::widgets::Clear, popup_area);
                 f.render_widget(help, popup_area);
             }
 
-            // CONTEXT MENU (MOUSE)
-            if let AppMode::ContextMenu { x, y, selected_index } = state.mode {
-                let items = vec![
-                    " New Box ",
-                    " New Diamond ",
-                    " New Text ",
-                    " New Frame ",
-                    "---------",
-                    " Start Connector ",
-                    " Start Arrow ",
-                    " Delete ",
-                    "---------",
-                    " Cancel "
+            // MESSAGE LOG (POPUP)
+            if let AppMode::Log(scroll) = state.mode {
+                let height = 20.min(area.height);
+                let visible = height.saturating_sub(2) as usize;
+                // Newest at the bottom; scrolling walks back in time
+                let end = state.message_log.len().saturating_sub(scroll);
+                let start = end.saturating_sub(visible);
+                let log_text: Vec<ratatui::text::Line> = state.message_log.range(start..end)
+                    .map(|entry| ratatui::text::Line::from(format!(" {}", entry)))
+                    .collect();
+                let popup_area = ratatui::layout::Rect {
+                    x: area.width.saturating_sub(60) / 2,
+                    y: (area.height / 2).saturating_sub(height / 2),
+                    width: 60.min(area.width),
+                    height,
+                };
+                let log_block = Block::default()
+                    .title(format!(" Message Log ({}) ", state.message_log.len()))
+                    .borders(Borders::ALL)
+                    .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::Cyan));
+                let log = Paragraph::new(log_text).block(log_block);
+                f.render_widget(ratatui::widgets::Clear, popup_area);
+                f.render_widget(log, popup_area);
+            }
+
+            // STATS (POPUP)
+            if state.mode == AppMode::Stats {
+                let count = |shape: ShapeType| state.nodes.iter().filter(|n| n.shape == shape).count();
+                let words: usize = state.nodes.iter().map(|n| n.text.split_whitespace().count()).sum();
+                let extent = match state.bounds() {
+                    Some((x, y, w, h)) => format!("{}x{} at ({}, {})", w, h, x, y),
+                    None => String::from("empty"),
+                };
+                let stats_text = vec![
+                    ratatui::text::Line::from(format!("  Boxes       : {}", count(ShapeType::Box))),
+                    ratatui::text::Line::from(format!("  Diamonds    : {}", count(ShapeType::Diamond))),
+                    ratatui::text::Line::from(format!("  Text        : {}", count(ShapeType::Text))),
+                    ratatui::text::Line::from(format!("  Frames      : {}", count(ShapeType::Frame))),
+                    ratatui::text::Line::from(format!("  Connections : {}", state.connections.len())),
+                    ratatui::text::Line::from(format!("  Words       : {}", words)),
+                    ratatui::text::Line::from(format!("  Extent      : {}", extent)),
+                    ratatui::text::Line::from(format!("  Created     : {}", state.created.as_deref().unwrap_or("not saved yet"))),
+                    ratatui::text::Line::from(format!("  Modified    : {}", state.modified.as_deref().unwrap_or("not saved yet"))),
+                    ratatui::text::Line::from(format!("  Author      : {}", state.author.as_deref().unwrap_or("unknown"))),
+                    ratatui::text::Line::from(""),
+                    ratatui::text::Line::from(ratatui::text::Span::styled("  Press <Esc> to close", ratatui::style::Style::default().fg(ratatui::style::Color::Yellow))),
                 ];
+                let height = stats_text.len() as u16 + 2;
+                let popup_area = ratatui::layout::Rect {
+                    x: area.width.saturating_sub(40) / 2,
+                    y: (area.height / 2).saturating_sub(height / 2),
+                    width: 40.min(area.width),
+                    height: height.min(area.height),
+                };
+                let stats_block = Block::default()
+                    .title(" Diagram Info ")
+                    .borders(Borders::ALL)
+                    .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::Cyan));
+                let stats = Paragraph::new(stats_text).block(stats_block);
+                f.render_widget(ratatui::widgets::Clear, popup_area);
+                f.render_widget(stats, popup_area);
+            }
+
+            // CONTEXT MENU (MOUSE)
+            if let AppMode::ContextMenu { x, y, selected_index, target } = state.mode {
+                let items: Vec<String> = target.items().iter().map(|item| match item.action() {
+                    Some(action) => format!(" {} ", action.label()),
+                    None => String::from("---------"),
+                }).collect();
                 
-                let width = 21;
+                let width = CONTEXT_MENU_WIDTH;
                 let height = items.len() as u16 + 2;
                 
                 // Adjust for terminal positioning
@@ -275,7 +1073,7 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                     .borders(Borders::ALL)
                     .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::White));
                 
-                let menu_text: Vec<ratatui::text::Line> = items.iter().enumerate().map(|(i, &item)| {
+                let menu_text: Vec<ratatui::text::Line> = items.iter().enumerate().map(|(i, item)| {
                     if i == selected_index {
                         ratatui::text::Line::from(ratatui::text::Span::styled(
                             format!("> {}", item),
@@ -292,36 +1090,54 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
             }
 
             // CURSOR
-            if let AppMode::Insert(id) = state.mode {
-                if let Some(node) = state.nodes.iter().find(|n| n.id == id) {
-                    let available_width = match node.shape {
-                        ShapeType::Box => node.width.saturating_sub(2),
-                        ShapeType::Diamond => node.width.saturating_sub(6).max(1),
-                        ShapeType::Text => node.width,
-                        ShapeType::Frame => node.width.saturating_sub(2),
-                    };
-                    let lines = crate::model::wrap_text(&node.text, available_width);
-                    let lines = if lines.is_empty() { vec![String::new()] } else { lines };
-                    let total_lines = lines.len() as u16;
-                    let (_available_height, start_y) = match node.shape {
-                        ShapeType::Text => (node.height, node.y),
-                        _ => {
-                            let ah = node.height.saturating_sub(2).max(1);
-                            let sy = node.y + 1 + (ah.saturating_sub(total_lines)) / 2;
-                            (ah, sy)
-                        }
-                    };
-                    
-                    let last_line_idx = lines.len().saturating_sub(1);
-                    let last_line = &lines[last_line_idx];
-                    let ty = start_y + last_line_idx as u16;
-                    let text_start_x = node.x + (node.width.saturating_sub(last_line.len() as u16)) / 2;
-                    let tx = text_start_x + last_line.len() as u16;
-                    cursor_pos = Some((inner_area_cache.x + tx, inner_area_cache.y + ty));
-                }
+            if let AppMode::Insert(id) = state.mode
+                && let Some(node) = state.nodes.iter().find(|n| n.id == id)
+                && node.shape == ShapeType::Diamond {
+                let (tx, ty) = match renderer::diamond_text_layout(node).last() {
+                    Some((x, y, line)) => (x + model::display_width(line) as i32, *y),
+                    None => node.center(),
+                };
+                cursor_pos = screen_cell(inner_area_cache, state.camera_offset, (tx, ty));
+            } else if let AppMode::Insert(id) = state.mode
+                && let Some(node) = state.nodes.iter().find(|n| n.id == id)
+                && node.shape == ShapeType::Frame {
+                // Just before the closing " ] " of the border title
+                let tx = match renderer::frame_title(node) {
+                    Some(title) => node.x + 2 + model::display_width(&title).saturating_sub(3) as i32,
+                    None => node.x + 5,
+                };
+                cursor_pos = screen_cell(inner_area_cache, state.camera_offset, (tx, node.y));
+            } else if let AppMode::Insert(id) = state.mode
+                && let Some(node) = state.nodes.iter().find(|n| n.id == id) {
+                let available_width = match node.shape {
+                    ShapeType::Box => node.width.saturating_sub(2),
+                    ShapeType::Diamond => node.width.saturating_sub(6).max(1),
+                    ShapeType::Text => node.width,
+                    ShapeType::Frame => node.width.saturating_sub(2),
+                };
+                let lines = model::wrap_text(&node.text, available_width);
+                let lines = if lines.is_empty() { vec![String::new()] } else { lines };
+                let total_lines = lines.len() as u16;
+                let (_available_height, start_y) = match node.shape {
+                    ShapeType::Text => (node.height, node.y),
+                    _ => {
+                        let ah = node.height.saturating_sub(2).max(1);
+                        let sy = node.y + 1 + ((ah.saturating_sub(total_lines)) / 2) as i32;
+                        (ah, sy)
+                    }
+                };
+                
+                let last_line_idx = lines.len().saturating_sub(1);
+                let last_line = &lines[last_line_idx];
+                let ty = start_y + last_line_idx as i32;
+                let last_width = model::display_width(last_line);
+                let text_start_x = node.x + ((node.width.saturating_sub(last_width)) / 2) as i32;
+                let tx = text_start_x + last_width as i32;
+                cursor_pos = screen_cell(inner_area_cache, state.camera_offset, (tx, ty));
             }
         })?;
 
+        let state = &mut docs[active];
         if let Some((cx, cy)) = cursor_pos {
             terminal.show_cursor()?;
             terminal.set_cursor_position((cx, cy))?;
@@ -330,25 +1146,30 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
         }
 
         if event::poll(Duration::from_millis(16))? {
+            // Only the paths that change something bump the version, so idle input keeps the cached canvas
             match event::read()? {
                 Event::Key(key) => {
+                    state.leader_since = None;
+                    if key.code == KeyCode::Esc && palette_armed.take().is_some() {
+                        status_msg = String::from("Shape placement cancelled");
+                        continue;
+                    }
                     match state.mode {
                         AppMode::Insert(id) => {
+                            // Only the very first key may overwrite a template
+                            let replace_template = state.template_pending.take() == Some(id);
                             match key.code {
                                 KeyCode::Esc => { 
                                     state.mode = AppMode::Normal; 
                                     for n in &mut state.nodes { n.selected = false; }
+                                    state.touch();
                                     continue; 
                                 }
                                 KeyCode::Tab => {
                                     state.mode = AppMode::Normal;
-                                    if !state.nodes.is_empty() {
-                                        let current_idx = state.nodes.iter().position(|n| n.id == id);
-                                        let next_idx = match current_idx {
-                                            Some(idx) => (idx + 1) % state.nodes.len(),
-                                            None => 0,
-                                        };
-                                        for (i, n) in state.nodes.iter_mut().enumerate() { n.selected = i == next_idx; }
+                                    state.touch();
+                                    if let Some(next_id) = state.next_in_reading_order(Some(id), false) {
+                                        for n in &mut state.nodes { n.selected = n.id == next_id; }
                                         state.selected_connection_index = None;
                                     }
                                     continue;
@@ -359,34 +1180,79 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                             if let Some(node) = state.nodes.iter_mut().find(|n| n.id == id) {
                                 match key.code {
                                     KeyCode::Char(c) => {
-                                        node.text.push(c);
-                                        if node.shape == ShapeType::Text {
-                                            let lines: Vec<&str> = node.text.split('\n').collect();
-                                            node.width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u16;
-                                            node.height = lines.len() as u16;
-                                        }
+                                        if replace_template { node.text.clear(); }
+                                        model::append_label(&mut node.text, c.encode_utf8(&mut [0; 4]), state.tab_width);
+                                        node.fit_to_text();
+                                        state.edit();
                                     }
                                     KeyCode::Backspace => {
-                                        node.text.pop();
-                                        if node.shape == ShapeType::Text {
-                                            let lines: Vec<&str> = node.text.split('\n').collect();
-                                            node.width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u16;
-                                            node.height = lines.len() as u16;
-                                        }
+                                        if replace_template { node.text.clear(); } else { node.text.pop(); }
+                                        node.fit_to_text();
+                                        state.edit();
+                                    }
+                                    KeyCode::Enter if node.shape == ShapeType::Frame => {
+                                        // Frame titles are a single line; Enter just finishes editing
+                                        state.mode = AppMode::Normal;
+                                        state.touch();
+                                        status_msg = String::from("Frame title set");
                                     }
                                     KeyCode::Enter => {
                                         node.text.push('\n');
-                                        if node.shape == ShapeType::Text {
-                                            let lines: Vec<&str> = node.text.split('\n').collect();
-                                            node.width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u16;
-                                            node.height = lines.len() as u16;
-                                        }
+                                        node.fit_to_text();
+                                        state.edit();
                                     }
                                     _ => {}
                                 }
                             } else {
                                 state.mode = AppMode::Normal;
+                                state.touch();
+                            }
+                        }
+                        AppMode::Label(idx) => {
+                            let Some(conn) = state.connections.get_mut(idx) else {
+                                state.mode = AppMode::Normal;
+                                state.touch();
+                                continue;
+                            };
+                            match key.code {
+                                KeyCode::Esc | KeyCode::Enter => {
+                                    status_msg = String::from(if conn.label.is_empty() { "Label cleared" } else { "Label set" });
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    continue;
+                                }
+                                KeyCode::Char(c) => model::append_label(&mut conn.label, c.encode_utf8(&mut [0; 4]), state.tab_width),
+                                KeyCode::Backspace => { conn.label.pop(); }
+                                _ => continue,
+                            }
+                            status_msg = label_prompt(&conn.label);
+                            state.edit();
+                        }
+                        AppMode::Geometry(id) => {
+                            match key.code {
+                                KeyCode::Esc => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = String::from("Placement cancelled");
+                                    continue;
+                                }
+                                KeyCode::Enter => {
+                                    // A bad entry stays up to be fixed; the node is only touched once it all parses
+                                    match model::Geometry::parse(&state.geometry_input).and_then(|g| state.apply_geometry(id, g)) {
+                                        Ok(()) => {
+                                            state.mode = AppMode::Normal;
+                                            state.edit();
+                                            status_msg = format!("Placed at {}", state.geometry_input.trim());
+                                        }
+                                        Err(err) => status_msg = format!("{} | {}", err, geometry_prompt(&state.geometry_input)),
+                                    }
+                                    continue;
+                                }
+                                KeyCode::Char(c) => state.geometry_input.push(c),
+                                KeyCode::Backspace => { state.geometry_input.pop(); }
+                                _ => {}
                             }
+                            status_msg = geometry_prompt(&state.geometry_input);
                         }
                         AppMode::Leader => {
                             match key.code {
@@ -396,184 +1262,368 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
 
                                     if let Some(last) = state.nodes.last() {
                                         spawn_x = last.x;
-                                        spawn_y = last.y + last.height + 2;
+                                        spawn_y = last.y + last.height as i32 + 2;
                                     }
 
-                                    let world_x = spawn_x as i32;
-                                    let world_y = spawn_y as i32;
-
-                                    let id = state.nodes.iter().map(|n| n.id).max().unwrap_or(0) + 1;
+                                    let id = state.next_id();
                                     let shape = match key.code {
                                         KeyCode::Char('n') => ShapeType::Box,
                                         KeyCode::Char('d') => ShapeType::Diamond,
                                         KeyCode::Char('f') => ShapeType::Frame,
                                         _ => ShapeType::Text,
                                     };
+                                    let (width, height) = shape.default_size();
+                                    let (x, y) = state.free_spot(shape, spawn_x, spawn_y, width, height);
                                     state.nodes.push(Node {
                                         id,
                                         shape,
-                                        x: world_x.max(0) as u16,
-                                        y: world_y.max(0) as u16,
-                                        width: if shape == ShapeType::Text { 10 } else if shape == ShapeType::Box { 20 } else if shape == ShapeType::Frame { 30 } else { 15 },
-                                        height: if shape == ShapeType::Text { 1 } else if shape == ShapeType::Box { 5 } else if shape == ShapeType::Frame { 10 } else { 7 },
-                                        text: String::new(),
+                                        x,
+                                        y,
+                                        width,
+                                        height,
+                                        text: state.templates.get(&shape).cloned().unwrap_or_default(),
                                         selected: true,
+                                        locked: false,
+                                        group: None,
+                                        style: NodeStyle::Plain,
+                                        shadow: false,
                                     });
+                                    state.template_pending = state.nodes.last().filter(|n| !n.text.is_empty()).map(|n| n.id);
+                                    if let Some(node) = state.nodes.last_mut() { node.fit_to_text(); }
                                     state.mode = AppMode::Insert(id);
                                     for n in &mut state.nodes { if n.id != id { n.selected = false; } }
                                     state.selected_connection_index = None;
+                                    state.edit();
                                     status_msg = String::from("New shape created below previous");
                                 }
                                 KeyCode::Char('h') => {
                                     state.mode = AppMode::Help;
+                                    state.touch();
                                 }
-                                KeyCode::Char('w') | KeyCode::Char('c') => {
-                                    if key.code == KeyCode::Char('c') {
-                                        let canvas = render_to_canvas(&state, 79, inner_area_cache.height);
-                                        let text = canvas.to_string();
-                                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                                            let _ = clipboard.set_text(text);
-                                            status_msg = String::from("Copied to clipboard!");
-                                        }
-                                    } else {
-                                        // Save ASCII .txt
-                                        let canvas = render_to_canvas(&state, 79, inner_area_cache.height);
-                                        let text = canvas.to_string();
-                                        let txt_filename = format!("{}.txt", state.title);
-                                        let _ = fs::write(&txt_filename, text);
-
-                                        // Save Model .json
-                                        let diagram = state.to_diagram();
-                                        if let Ok(json) = serde_json::to_string_pretty(&diagram) {
-                                            let json_filename = format!("{}.json", state.title);
-                                            if fs::write(&json_filename, json).is_ok() {
-                                                status_msg = format!("Saved {} and {}!", txt_filename, json_filename);
-                                            }
-                                        }
-                                    }
-                                    state.mode = AppMode::Normal;
+                                KeyCode::Char('i') => {
+                                    state.mode = AppMode::Stats;
+                                    state.touch();
                                 }
-                                KeyCode::Char('q') => return Ok(()),
-                                KeyCode::Esc => { state.mode = AppMode::Normal; }
-                                _ => {}
+                                KeyCode::Char('m') => {
+                                    state.mode = AppMode::Log(0);
+                                    state.touch();
+                                }
+                                KeyCode::Char('s') => {
+                                    state.export_selection = !state.export_selection;
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = if state.export_selection {
+                                        String::from("Export: selection only (w/c crop to the selected shapes)")
+                                    } else {
+                                        String::from("Export: whole view")
+                                    };
+                                }
+                                KeyCode::Tab | KeyCode::BackTab => {
+                                    status_msg = switch_tab(&mut docs, &mut active, key.code == KeyCode::BackTab);
+                                    continue;
+                                }
+                                KeyCode::Char('v') => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    // The next tab goes alongside, or a second view of this diagram if it's the only one
+                                    split = match split {
+                                        Some(_) => {
+                                            status_msg = String::from("Split closed");
+                                            None
+                                        }
+                                        None => {
+                                            let other = (active + 1) % docs.len();
+                                            status_msg = format!("Split: {} | <Leader> o or click a pane to switch focus", docs[other].display_title());
+                                            Some(Split { other, camera: docs[other].camera_offset, focus_right: false })
+                                        }
+                                    };
+                                }
+                                KeyCode::Char('o') => {
+                                    match split.as_mut() {
+                                        Some(split) => status_msg = switch_focus(&mut docs, &mut active, split),
+                                        None => {
+                                            state.mode = AppMode::Normal;
+                                            state.touch();
+                                            status_msg = String::from("No split open (<Leader> v)");
+                                        }
+                                    }
+                                }
+                                KeyCode::Char('l') => {
+                                    // Click-linking picks its own source; drop any pending keyboard one
+                                    state.click_connect = true;
+                                    state.connection_source_id = None;
+                                    state.connection_source_offset = None;
+                                    state.connection_has_arrow = true;
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = String::from("Link: click the source shape, then the target (Esc cancels)");
+                                }
+                                KeyCode::Char('p') => {
+                                    show_palette = !show_palette;
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    let panes = if split.is_some() { 2 } else { 1 };
+                                    let fits = area.width.saturating_sub(79 * panes) / 2 >= PALETTE_WIDTH;
+                                    status_msg = match (show_palette, fits) {
+                                        (false, _) => {
+                                            palette_armed = None;
+                                            String::from("Palette hidden")
+                                        }
+                                        (true, true) => String::from("Palette: click a shape, then click the canvas to place it"),
+                                        (true, false) => format!("Palette on, but needs {} free columns left of the canvas; widen the terminal", PALETTE_WIDTH),
+                                    };
+                                }
+                                KeyCode::Char('L') => {
+                                    state.legend.visible = !state.legend.visible;
+                                    state.mode = AppMode::Normal;
+                                    state.edit();
+                                    status_msg = match (state.legend.visible, state.legend.entries.is_empty()) {
+                                        (false, _) => String::from("Legend hidden"),
+                                        (true, false) => String::from("Legend shown"),
+                                        (true, true) => format!("Legend on, but it has no entries yet; add them under \"legend\" in {}", state.file_name("json")),
+                                    };
+                                }
+                                KeyCode::Char('K') => {
+                                    state.legend.corner = state.legend.corner.next();
+                                    state.mode = AppMode::Normal;
+                                    state.edit();
+                                    status_msg = format!("Legend corner: {:?}", state.legend.corner);
+                                }
+                                KeyCode::Char('a') => {
+                                    state.snap_links = !state.snap_links;
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = if state.snap_links {
+                                        String::from("New links between nearly aligned shapes snap straight")
+                                    } else {
+                                        String::from("New links keep their facing anchors")
+                                    };
+                                }
+                                KeyCode::Char('T') => {
+                                    let moved = state.tidy();
+                                    state.mode = AppMode::Normal;
+                                    if moved == 0 { state.touch(); } else { state.edit(); }
+                                    status_msg = if moved == 0 {
+                                        String::from("No overlapping shapes")
+                                    } else {
+                                        format!("Tidied: moved {} shape(s) apart", moved)
+                                    };
+                                }
+                                KeyCode::Char('r') => {
+                                    let changed = state.compact_ids();
+                                    state.mode = AppMode::Normal;
+                                    if changed == 0 { state.touch(); } else { state.edit(); }
+                                    status_msg = if changed == 0 {
+                                        String::from("Node ids already run 1..N")
+                                    } else {
+                                        format!("Renumbered {} node(s) to 1..{}", changed, state.nodes.len())
+                                    };
+                                }
+                                KeyCode::Char('C') => {
+                                    if let Ok(mut clipboard) = arboard::Clipboard::new() {
+                                        let _ = clipboard.set_text(state.to_markdown_summary());
+                                        status_msg = String::from("Copied Markdown summary to clipboard!");
+                                    }
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                }
+                                KeyCode::Char('w') | KeyCode::Char('c') => {
+                                    if key.code == KeyCode::Char('c') {
+                                        let text = export_text(state);
+                                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
+                                            let _ = clipboard.set_text(text);
+                                            status_msg = String::from("Copied to clipboard!");
+                                        }
+                                    } else {
+                                        status_msg = save(state);
+                                    }
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                }
+                                KeyCode::Char('q') => {
+                                    if !ask_next_unsaved(&mut docs, 0, &mut active, &mut status_msg) {
+                                        return Ok(());
+                                    }
+                                    continue;
+                                }
+                                KeyCode::Esc => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                }
+                                _ => {}
                             }
                         }
-                        AppMode::Help => {
+                        AppMode::ConfirmQuit => {
+                            match key.code {
+                                KeyCode::Char('y') | KeyCode::Char('n') => {
+                                    if key.code == KeyCode::Char('y') {
+                                        status_msg = save(state);
+                                        // A failed save stops the quit so nothing is lost
+                                        if state.is_dirty() {
+                                            state.mode = AppMode::Normal;
+                                            state.touch();
+                                            continue;
+                                        }
+                                    }
+                                    if !ask_next_unsaved(&mut docs, active + 1, &mut active, &mut status_msg) {
+                                        return Ok(());
+                                    }
+                                }
+                                KeyCode::Esc => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = String::from("Quit cancelled");
+                                }
+                                _ => {}
+                            }
+                        }
+                        AppMode::ConfirmReload => {
+                            match key.code {
+                                KeyCode::Char('y') => status_msg = reload(state),
+                                KeyCode::Char('n') | KeyCode::Esc => {
+                                    // Settle on this disk version so it isn't asked about again
+                                    state.disk_mtime = json_mtime(state);
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = format!("Kept your changes; saving will overwrite {}", state.file_name("json"));
+                                }
+                                _ => {}
+                            }
+                        }
+                        AppMode::Help | AppMode::Stats => {
                             match key.code {
                                 KeyCode::Esc | KeyCode::Char(' ') | KeyCode::Enter => {
                                     state.mode = AppMode::Normal;
+                                    state.touch();
                                 }
                                 _ => {}
                             }
                         }
+                        AppMode::Log(scroll) => {
+                            match key.code {
+                                KeyCode::Up if scroll + 1 < state.message_log.len() => {
+                                    state.mode = AppMode::Log(scroll + 1);
+                                    state.touch();
+                                }
+                                KeyCode::Down => {
+                                    state.mode = AppMode::Log(scroll.saturating_sub(1));
+                                    state.touch();
+                                }
+                                KeyCode::Esc | KeyCode::Char(' ') | KeyCode::Enter => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                }
+                                _ => {}
+                            }
+                        }
+                        AppMode::QuickConnect { from, typed } => {
+                            // Digits pick a badge; a number that can't grow into another id is taken at once
+                            let chosen = match key.code {
+                                KeyCode::Esc => {
+                                    state.mode = AppMode::Normal;
+                                    state.touch();
+                                    status_msg = String::from("Quick connect cancelled");
+                                    continue;
+                                }
+                                KeyCode::Backspace => {
+                                    let typed = typed.map(|t| t / 10).filter(|&t| t > 0);
+                                    state.mode = AppMode::QuickConnect { from, typed };
+                                    state.touch();
+                                    None
+                                }
+                                KeyCode::Char(c) if c.is_ascii_digit() => {
+                                    let number = typed.unwrap_or(0).saturating_mul(10) + c.to_digit(10).unwrap_or(0) as usize;
+                                    let longer = state.nodes.iter().any(|n| n.id != number && n.id.to_string().starts_with(&number.to_string()));
+                                    state.mode = AppMode::QuickConnect { from, typed: Some(number) };
+                                    state.touch();
+                                    if longer { None } else { Some(number) }
+                                }
+                                KeyCode::Enter | KeyCode::Char(' ') => typed,
+                                _ => None,
+                            };
+                            if let Some(number) = chosen {
+                                if !state.nodes.iter().any(|n| n.id == number) {
+                                    state.mode = AppMode::QuickConnect { from, typed: None };
+                                    status_msg = format!("No shape #{}", number);
+                                    continue;
+                                }
+                                match from {
+                                    None => {
+                                        state.mode = AppMode::QuickConnect { from: Some(number), typed: None };
+                                        state.touch();
+                                    }
+                                    Some(src_id) if src_id != number => {
+                                        state.connect(src_id, number, state.connection_has_arrow);
+                                        state.mode = AppMode::Normal;
+                                        state.edit();
+                                        status_msg = format!("Linked #{} to #{}", src_id, number);
+                                        continue;
+                                    }
+                                    Some(_) => {
+                                        state.mode = AppMode::QuickConnect { from, typed: None };
+                                        status_msg = String::from("Pick a different target");
+                                        continue;
+                                    }
+                                }
+                            }
+                            if let AppMode::QuickConnect { from, typed } = state.mode {
+                                let so_far = typed.map_or(String::new(), |t| t.to_string());
+                                status_msg = match from {
+                                    None => format!("Quick connect: source #{}_ (Enter confirms, Esc aborts)", so_far),
+                                    Some(src) => format!("Quick connect: #{} -> #{}_ (Enter confirms, Esc aborts)", src, so_far),
+                                };
+                            }
+                        }
                         AppMode::Resize(id) => {
                             if let Some(node) = state.nodes.iter_mut().find(|n| n.id == id) {
+                                let (min_w, min_h) = node.shape.min_size();
                                 match key.code {
                                     KeyCode::Char('+') | KeyCode::Char('=') => {
-                                        node.width += 2;
-                                        node.height += 1;
-                                        status_msg = format!("Resized: {}x{}", node.width, node.height);
+                                        node.width = node.width.saturating_add(2);
+                                        node.height = node.height.saturating_add(1);
                                     }
                                     KeyCode::Char('-') | KeyCode::Char('_') => {
-                                        node.width = (node.width.saturating_sub(2)).max(3);
-                                        node.height = (node.height.saturating_sub(1)).max(1);
-                                        status_msg = format!("Resized: {}x{}", node.width, node.height);
+                                        node.width = node.width.saturating_sub(2).max(min_w);
+                                        node.height = node.height.saturating_sub(1).max(min_h);
                                     }
+                                    // Arrows change one dimension at a time
+                                    KeyCode::Right => node.width = node.width.saturating_add(1),
+                                    KeyCode::Left => node.width = node.width.saturating_sub(1).max(min_w),
+                                    KeyCode::Down => node.height = node.height.saturating_add(1),
+                                    KeyCode::Up => node.height = node.height.saturating_sub(1).max(min_h),
                                     KeyCode::Esc | KeyCode::Enter => {
                                         state.mode = AppMode::Normal;
+                                        state.touch();
                                         status_msg = String::from("Resize finished");
+                                        continue;
                                     }
-                                    _ => {}
+                                    _ => continue,
                                 }
+                                status_msg = format!("Resized: {}x{}", node.width, node.height);
+                                state.edit();
                             } else {
                                 state.mode = AppMode::Normal;
+                                state.touch();
                             }
                         }
-                        AppMode::ContextMenu { x, y, mut selected_index } => {
+                        AppMode::ContextMenu { x, y, selected_index, target } => {
+                            let items = target.items();
                             match key.code {
-                                KeyCode::Up => {
-                                    if selected_index > 0 {
-                                        selected_index -= 1;
-                                        if selected_index == 4 || selected_index == 8 { selected_index -= 1; }
-                                        state.mode = AppMode::ContextMenu { x, y, selected_index };
-                                    }
-                                }
-                                KeyCode::Down => {
-                                    if selected_index < 9 {
-                                        selected_index += 1;
-                                        if selected_index == 4 || selected_index == 8 { selected_index += 1; }
-                                        state.mode = AppMode::ContextMenu { x, y, selected_index };
-                                    }
+                                KeyCode::Up | KeyCode::Down => {
+                                    let selected_index = model::step_menu(&items, selected_index, key.code == KeyCode::Down);
+                                    state.mode = AppMode::ContextMenu { x, y, selected_index, target };
+                                    state.touch();
                                 }
                                 KeyCode::Enter | KeyCode::Char(' ') => {
-                                    let id = state.nodes.iter().map(|n| n.id).max().unwrap_or(0) + 1;
-                                    let world_x = (x as i32 + state.camera_offset.0).max(0) as u16;
-                                    let world_y = (y as i32 + state.camera_offset.1).max(0) as u16;
-                                    
-                                    match selected_index {
-                                        0 => { // New Box
-                                            state.nodes.push(Node { id, shape: ShapeType::Box, x: world_x, y: world_y, width: 20, height: 5, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        1 => { // New Diamond
-                                            state.nodes.push(Node { id, shape: ShapeType::Diamond, x: world_x, y: world_y, width: 15, height: 7, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        2 => { // New Text
-                                            state.nodes.push(Node { id, shape: ShapeType::Text, x: world_x, y: world_y, width: 10, height: 1, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        3 => { // New Frame
-                                            state.nodes.push(Node { id, shape: ShapeType::Frame, x: world_x, y: world_y, width: 30, height: 10, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        5 => { // Start Connector
-                                            if let Some(node) = state.nodes.iter().rev().find(|n| n.contains(world_x, world_y)) {
-                                                state.connection_source_id = Some(node.id);
-                                                state.connection_has_arrow = false;
-                                                status_msg = format!("Connector source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
-                                            } else {
-                                                status_msg = String::from("No node at click position");
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        6 => { // Start Arrow
-                                            if let Some(node) = state.nodes.iter().rev().find(|n| n.contains(world_x, world_y)) {
-                                                state.connection_source_id = Some(node.id);
-                                                state.connection_has_arrow = true;
-                                                status_msg = format!("Arrow source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
-                                            } else {
-                                                status_msg = String::from("No node at click position");
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        7 => { // Delete
-                                            if let Some(idx) = state.nodes.iter().position(|n| n.contains(world_x, world_y)) {
-                                                let node_id = state.nodes[idx].id;
-                                                state.nodes.remove(idx);
-                                                state.connections.retain(|c| c.from_id != node_id && c.to_id != node_id);
-                                                status_msg = String::from("Shape and connections deleted");
-                                            } else {
-                                                for (i, conn) in state.connections.iter().enumerate().rev() {
-                                                    if conn.contains(world_x, world_y, &state.nodes) {
-                                                        state.connections.remove(i);
-                                                        status_msg = String::from("Connection deleted");
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        9 => { state.mode = AppMode::Normal; }
-                                        _ => { state.mode = AppMode::Normal; }
-                                    }
-                                    if selected_index < 4 {
-                                        for n in &mut state.nodes { if n.id != id { n.selected = false; } }
-                                        state.selected_connection_index = None;
+                                    let world_x = x as i32 + state.camera_offset.0;
+                                    let world_y = y as i32 + state.camera_offset.1;
+                                    if let Some(action) = items.get(selected_index).and_then(|item| item.action()) {
+                                        status_msg = apply_menu_action(state, action, target, (world_x, world_y)).unwrap_or(status_msg);
                                     }
                                 }
                                 KeyCode::Esc => {
                                     state.mode = AppMode::Normal;
+                                    state.touch();
                                 }
                                 _ => {}
                             }
@@ -581,158 +1631,455 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                         AppMode::Normal => {
                             match key.code {
                                 KeyCode::Esc => {
-                                    state.connection_source_id = None;
-                                    state.selected_connection_index = None;
-                                    for n in &mut state.nodes { n.selected = false; }
-                                    status_msg = String::from("Selection cleared");
+                                    // Each Esc backs out one layer: pending link, then connection, then shapes
+                                    if state.connection_source_id.is_some() || state.click_connect {
+                                        state.connection_source_id = None;
+                                        state.connection_source_offset = None;
+                                        state.click_connect = false;
+                                        state.touch();
+                                        status_msg = String::from("Connection cancelled");
+                                    } else if state.selected_connection_index.is_some() {
+                                        state.selected_connection_index = None;
+                                        state.touch();
+                                        status_msg = String::from("Connection deselected");
+                                    } else if state.nodes.iter().any(|n| n.selected) {
+                                        for n in &mut state.nodes { n.selected = false; }
+                                        state.touch();
+                                        status_msg = String::from("Selection cleared");
+                                    }
+                                }
+                                KeyCode::Char(' ') => {
+                                    state.mode = AppMode::Leader;
+                                    state.touch();
+                                }
+                                KeyCode::Char('q') => {
+                                    if !ask_next_unsaved(&mut docs, 0, &mut active, &mut status_msg) {
+                                        return Ok(());
+                                    }
+                                    continue;
+                                }
+                                KeyCode::Char('i') if state.selected_connection_index.is_some_and(|idx| idx < state.connections.len()) => {
+                                    if let Some(idx) = state.selected_connection_index {
+                                        state.mode = AppMode::Label(idx);
+                                        state.touch();
+                                        status_msg = label_prompt(&state.connections[idx].label);
+                                    }
                                 }
-                                KeyCode::Char(' ') => { state.mode = AppMode::Leader; }
-                                KeyCode::Char('q') => return Ok(()),
                                 KeyCode::Char('i') => {
                                     if let Some(node) = state.nodes.iter().find(|n| n.selected) {
-                                        state.mode = AppMode::Insert(node.id);
+                                        if node.locked {
+                                            status_msg = String::from("Shape is locked");
+                                        } else {
+                                            state.mode = AppMode::Insert(node.id);
+                                            state.touch();
+                                        }
                                     }
                                 }
-                                KeyCode::Tab => {
-                                    if !state.nodes.is_empty() {
-                                        let current_idx = state.nodes.iter().position(|n| n.selected);
-                                        let next_idx = match current_idx {
-                                            Some(idx) => (idx + 1) % state.nodes.len(),
-                                            None => 0,
+                                KeyCode::Char('l') => {
+                                    if let Some(node) = state.nodes.iter_mut().find(|n| n.selected) {
+                                        node.locked = !node.locked;
+                                        status_msg = if node.locked { String::from("Shape locked") } else { String::from("Shape unlocked") };
+                                        state.edit();
+                                    }
+                                }
+                                KeyCode::Char('s') => {
+                                    let mut styled = None;
+                                    for n in state.nodes.iter_mut().filter(|n| n.selected && !n.locked) {
+                                        n.style = n.style.next();
+                                        styled = Some(n.style);
+                                    }
+                                    if styled.is_some() { state.edit(); }
+                                    status_msg = match styled {
+                                        Some(style) => format!("Border style: {:?}", style),
+                                        None => String::from("Select an unlocked shape to restyle"),
+                                    };
+                                }
+                                KeyCode::Char('S') => {
+                                    let mut shaded = None;
+                                    for n in state.nodes.iter_mut().filter(|n| n.selected && !n.locked && n.shape == ShapeType::Box) {
+                                        n.shadow = !n.shadow;
+                                        shaded = Some(n.shadow);
+                                    }
+                                    if shaded.is_some() { state.edit(); }
+                                    status_msg = match shaded {
+                                        Some(true) => String::from("Shadow on"),
+                                        Some(false) => String::from("Shadow off"),
+                                        None => String::from("Select an unlocked box for a shadow"),
+                                    };
+                                }
+                                KeyCode::Char('=') => {
+                                    match state.nodes.iter().find(|n| n.selected) {
+                                        Some(node) if node.locked => status_msg = String::from("Shape is locked"),
+                                        Some(node) => {
+                                            // Start from where it is so a single field is quick to change
+                                            state.geometry_input = model::Geometry::of(node).to_string();
+                                            state.mode = AppMode::Geometry(node.id);
+                                            state.touch();
+                                            status_msg = geometry_prompt(&state.geometry_input);
+                                        }
+                                        None => status_msg = String::from("Select a shape to place it exactly"),
+                                    }
+                                }
+                                KeyCode::Char('y') => {
+                                    status_msg = if state.copy_style() { String::from("Style copied") } else { String::from("Select a shape to copy its style") };
+                                }
+                                KeyCode::Char('P') => {
+                                    status_msg = match (state.style_buffer, state.paste_style()) {
+                                        (None, _) => String::from("No style copied yet (y)"),
+                                        (Some(_), 0) => String::from("Select unlocked shapes to paste the style onto"),
+                                        (Some(_), count) => {
+                                            state.edit();
+                                            format!("Style pasted onto {} shape(s)", count)
+                                        }
+                                    };
+                                }
+                                KeyCode::Char('T') => {
+                                    if let Some(node) = state.nodes.iter().find(|n| n.selected) {
+                                        let (shape, text) = (node.shape, node.text.clone());
+                                        status_msg = if text.is_empty() {
+                                            state.templates.remove(&shape);
+                                            format!("New {:?} shapes start empty", shape)
+                                        } else {
+                                            state.templates.insert(shape, text);
+                                            format!("New {:?} shapes start with this text", shape)
                                         };
-                                        for (i, n) in state.nodes.iter_mut().enumerate() { n.selected = i == next_idx; }
-                                        state.selected_connection_index = None;
+                                        state.edit();
                                     }
                                 }
-                                KeyCode::BackTab => {
-                                    if !state.nodes.is_empty() {
-                                        let current_idx = state.nodes.iter().position(|n| n.selected);
-                                        let next_idx = match current_idx {
-                                            Some(idx) => (idx + state.nodes.len() - 1) % state.nodes.len(),
-                                            None => state.nodes.len() - 1,
+                                KeyCode::Char('E') => {
+                                    state.edges_behind = !state.edges_behind;
+                                    state.edit();
+                                    status_msg = if state.edges_behind { String::from("Connections drawn behind shapes") } else { String::from("Connections drawn over shapes") };
+                                }
+                                KeyCode::Char('M') => {
+                                    state.show_minimap = !state.show_minimap;
+                                    state.touch();
+                                    status_msg = if state.show_minimap { String::from("Mini-map shown") } else { String::from("Mini-map hidden") };
+                                }
+                                KeyCode::Char('#') => {
+                                    state.show_ids = !state.show_ids;
+                                    state.touch();
+                                    status_msg = if state.show_ids { String::from("Showing node ids") } else { String::from("Node ids hidden") };
+                                }
+                                KeyCode::Char('k') => {
+                                    state.mode = AppMode::QuickConnect { from: None, typed: None };
+                                    state.touch();
+                                    status_msg = String::from("Quick connect: type the source number (Esc aborts)");
+                                }
+                                KeyCode::Char('x') => {
+                                    if let Some(node) = state.nodes.iter().find(|n| n.selected && !n.locked) {
+                                        let shape = node.shape.next();
+                                        let count = state.convert_selected(shape);
+                                        state.edit();
+                                        status_msg = format!("Converted {} shape(s) to {:?}", count, shape);
+                                    } else {
+                                        status_msg = String::from("Select an unlocked shape to convert");
+                                    }
+                                }
+                                KeyCode::Char('v') => {
+                                    if let Some(conn) = state.selected_connection_index.and_then(|idx| state.connections.get_mut(idx)) {
+                                        status_msg = if conn.reverse() {
+                                            state.edit();
+                                            String::from("Connection reversed")
+                                        } else {
+                                            String::from("Can't reverse a link to a free point")
+                                        };
+                                    } else {
+                                        status_msg = String::from("Select a connection to reverse");
+                                    }
+                                }
+                                KeyCode::Char('b') => {
+                                    if let Some(idx) = state.selected_connection_index.filter(|&idx| idx < state.connections.len()) {
+                                        let nodes = &state.nodes;
+                                        let conn = &mut state.connections[idx];
+                                        if conn.is_straight(nodes) {
+                                            status_msg = String::from("Connection is already straight");
+                                            continue;
+                                        }
+                                        status_msg = if conn.straighten(nodes) {
+                                            String::from("Connection straightened")
+                                        } else {
+                                            // No straight line fits; move the bend instead
+                                            conn.bend = conn.bend.next();
+                                            conn.bend.describe()
                                         };
-                                        for (i, n) in state.nodes.iter_mut().enumerate() { n.selected = i == next_idx; }
+                                        state.edit();
+                                    } else {
+                                        status_msg = String::from("Select a connection to straighten");
+                                    }
+                                }
+                                KeyCode::Char('[') | KeyCode::Char(']') => {
+                                    if let Some(conn) = state.selected_connection_index.and_then(|idx| state.connections.get_mut(idx)) {
+                                        conn.bend = conn.bend.shifted(if key.code == KeyCode::Char('[') { -BEND_STEP } else { BEND_STEP });
+                                        status_msg = conn.bend.describe();
+                                        state.edit();
+                                    } else {
+                                        status_msg = String::from("Select a connection to move its bend");
+                                    }
+                                }
+                                KeyCode::Char('w') => {
+                                    if let Some(conn) = state.selected_connection_index.and_then(|idx| state.connections.get_mut(idx)) {
+                                        conn.weight = conn.weight.next();
+                                        status_msg = format!("Connection weight: {:?}", conn.weight);
+                                        state.edit();
+                                    } else {
+                                        status_msg = String::from("Select a connection to change its weight");
+                                    }
+                                }
+                                KeyCode::Char('g') => {
+                                    status_msg = match state.group_selected() {
+                                        Some(count) => {
+                                            state.edit();
+                                            format!("Grouped {} shapes", count)
+                                        }
+                                        None => String::from("Select at least two shapes to group"),
+                                    };
+                                }
+                                KeyCode::Char('G') => {
+                                    let count = state.ungroup_selected();
+                                    if count > 0 { state.edit(); }
+                                    status_msg = if count > 0 { format!("Ungrouped {} shapes", count) } else { String::from("Selection isn't grouped") };
+                                }
+                                KeyCode::Char('F') => {
+                                    if let Some(frame) = state.nodes.iter().find(|n| n.selected && n.shape == ShapeType::Frame) {
+                                        let frame_id = frame.id;
+                                        status_msg = if frame.locked {
+                                            String::from("Shape is locked")
+                                        } else if state.fit_frame_to_contents(frame_id) {
+                                            state.edit();
+                                            String::from("Frame fitted to contents")
+                                        } else {
+                                            String::from("Frame is empty, nothing to fit")
+                                        };
+                                    } else {
+                                        status_msg = String::from("Select a frame to fit");
+                                    }
+                                }
+                                KeyCode::Tab | KeyCode::BackTab if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                    status_msg = switch_tab(&mut docs, &mut active, key.code == KeyCode::BackTab);
+                                    continue;
+                                }
+                                KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                    match state.last_selected_id {
+                                        Some(id) => {
+                                            for n in &mut state.nodes { n.selected = n.id == id; }
+                                            state.selected_connection_index = None;
+                                            state.touch();
+                                            let name = state.nodes.iter().find(|n| n.id == id).and_then(|n| n.text.split_whitespace().next()).unwrap_or("Node");
+                                            status_msg = format!("Back to #{} {}", id, name);
+                                        }
+                                        None => status_msg = String::from("No previous selection"),
+                                    }
+                                }
+                                KeyCode::Tab | KeyCode::BackTab => {
+                                    let current_id = state.nodes.iter().find(|n| n.selected).map(|n| n.id);
+                                    if let Some(next_id) = state.next_in_reading_order(current_id, key.code == KeyCode::BackTab) {
+                                        for n in &mut state.nodes { n.selected = n.id == next_id; }
                                         state.selected_connection_index = None;
+                                        state.touch();
                                     }
                                 }
                                 KeyCode::Char('r') => {
                                     if let Some(node) = state.nodes.iter().find(|n| n.selected) {
+                                        if node.locked {
+                                            status_msg = String::from("Shape is locked");
+                                            continue;
+                                        }
+                                        if !node.shape.resizable() {
+                                            status_msg = String::from(TEXT_RESIZE_MSG);
+                                            continue;
+                                        }
                                         state.mode = AppMode::Resize(node.id);
-                                        status_msg = String::from("Resize Mode: Use +/- to scale, Esc to finish");
+                                        status_msg = format!("Resize Mode ({}x{}): +/- to scale, arrows for width/height, Esc to finish", node.width, node.height);
+                                        state.touch();
                                     }
                                 }
                                 KeyCode::Delete | KeyCode::Backspace => {
                                     if let Some(idx) = state.selected_connection_index {
                                         state.connections.remove(idx);
                                         state.selected_connection_index = None;
+                                        state.edit();
                                         status_msg = String::from("Connection deleted");
-                                    } else if let Some(idx) = state.nodes.iter().position(|n| n.selected) {
-                                        let node_id = state.nodes[idx].id;
-                                        state.nodes.remove(idx);
-                                        state.connections.retain(|c| c.from_id != node_id && c.to_id != node_id);
+                                    } else if state.nodes.iter().any(|n| n.selected) {
+                                        let doomed: Vec<usize> = state.nodes.iter().filter(|n| n.selected && !n.locked).map(|n| n.id).collect();
+                                        if doomed.is_empty() {
+                                            status_msg = String::from("Shape is locked");
+                                            continue;
+                                        }
+                                        state.nodes.retain(|n| !doomed.contains(&n.id));
+                                        state.connections.retain(|c| !doomed.iter().any(|&id| c.touches(id)));
+                                        state.edit();
                                         status_msg = String::from("Shape and connections deleted");
+                                    } else if let Some((hx, hy)) = state.hover_pos
+                                        && let Some(idx) = state.connection_near(hx, hy) {
+                                        // Nothing selected: prune the edge under the mouse
+                                        state.connections.remove(idx);
+                                        state.edit();
+                                        status_msg = String::from("Connection deleted");
                                     }
                                 }
                                 KeyCode::Char('c') => {
-                                    if let Some(node) = state.nodes.iter().find(|n| n.selected) {
-                                        state.connection_source_id = Some(node.id);
-                                        state.connection_has_arrow = false;
-                                        status_msg = format!("Connector source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
+                                    if let Some(id) = state.nodes.iter().find(|n| n.selected).map(|n| n.id)
+                                        && let Some(msg) = state.start_connection(id, false) {
+                                        state.touch();
+                                        status_msg = msg;
                                     }
                                 }
                                 KeyCode::Enter => {
-                                    if let Some(src_id) = state.connection_source_id {
-                                        if let Some(target_node) = state.nodes.iter().find(|n| n.selected) {
-                                            if target_node.id != src_id {
-                                                if let Some(src_node) = state.nodes.iter().find(|n| n.id == src_id) {
-                                                    // Smart heuristic based on relative position
-                                                    let from_offset;
-                                                    let to_offset;
-                                                    
-                                                    if target_node.y >= src_node.y + src_node.height {
-                                                        // Target is below
-                                                        from_offset = (src_node.width / 2, src_node.height - 1);
-                                                        to_offset = (target_node.width / 2, 0);
-                                                    } else if target_node.x >= src_node.x + src_node.width {
-                                                        // Target is to the right
-                                                        from_offset = (src_node.width - 1, src_node.height / 2);
-                                                        to_offset = (0, target_node.height / 2);
-                                                    } else if src_node.y >= target_node.y + target_node.height {
-                                                        // Target is above
-                                                        from_offset = (src_node.width / 2, 0);
-                                                        to_offset = (target_node.width / 2, target_node.height - 1);
-                                                    } else {
-                                                        // Target is to the left
-                                                        from_offset = (0, src_node.height / 2);
-                                                        to_offset = (target_node.width - 1, target_node.height / 2);
-                                                    }
-
-                                                    state.connections.push(crate::model::Connection {
-                                                        from_id: src_id,
-                                                        from_offset,
-                                                        to_id: target_node.id,
-                                                        to_offset,
-                                                        has_arrow: state.connection_has_arrow,
-                                                    });
-                                                    state.connection_source_id = None;
-                                                    status_msg = String::from("Keyboard connection created!");
-                                                }
-                                            }
-                                        }
+                                    if let Some(src_id) = state.connection_source_id
+                                        && let Some(target_id) = state.nodes.iter().find(|n| n.selected).map(|n| n.id)
+                                        && state.connect(src_id, target_id, state.connection_has_arrow) {
+                                        state.connection_source_id = None;
+                                        state.connection_source_offset = None;
+                                        state.edit();
+                                        status_msg = String::from("Keyboard connection created!");
                                     }
                                 }
                                 KeyCode::Char('a') => {
                                     if let Some(idx) = state.selected_connection_index {
-                                        state.connections[idx].has_arrow = !state.connections[idx].has_arrow;
-                                        status_msg = if state.connections[idx].has_arrow { String::from("Arrow enabled") } else { String::from("Arrow disabled") };
-                                    } else if let Some(node) = state.nodes.iter().find(|n| n.selected) {
-                                        state.connection_source_id = Some(node.id);
-                                        state.connection_has_arrow = true;
-                                        status_msg = format!("Arrow source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
+                                        status_msg = String::from(state.connections[idx].cycle_arrows());
+                                        state.edit();
+                                    } else if let Some(id) = state.nodes.iter().find(|n| n.selected).map(|n| n.id)
+                                        && let Some(msg) = state.start_connection(id, true) {
+                                        state.touch();
+                                        status_msg = msg;
                                     } else {
                                         status_msg = String::from("Select a node (a) for Arrow or connection (a) to toggle");
                                     }
                                 }
+                                KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                    let (dx, dy) = match key.code {
+                                        KeyCode::Up => (0, -1),
+                                        KeyCode::Down => (0, 1),
+                                        KeyCode::Left => (-1, 0),
+                                        _ => (1, 0),
+                                    };
+                                    let current_id = state.nodes.iter().find(|n| n.selected).map(|n| n.id);
+                                    let target = match current_id {
+                                        Some(id) => state.nearest_in_direction(id, dx, dy),
+                                        None => state.next_in_reading_order(None, false),
+                                    };
+                                    if let Some(target_id) = target {
+                                        for n in &mut state.nodes { n.selected = n.id == target_id; }
+                                        state.selected_connection_index = None;
+                                        state.touch();
+                                    }
+                                }
                                 KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
-                                    if let Some(node) = state.nodes.iter_mut().find(|n| n.selected) {
-                                        match key.code {
-                                            KeyCode::Up => node.y = node.y.saturating_sub(1),
-                                            KeyCode::Down => node.y += 1,
-                                            KeyCode::Left => node.x = node.x.saturating_sub(1),
-                                            KeyCode::Right => node.x += 1,
-                                            _ => {}
+                                    // Shift nudges by the larger step
+                                    let step = if key.modifiers.contains(KeyModifiers::SHIFT) { state.nudge_step as i32 } else { 1 };
+                                    let (dx, dy) = match key.code {
+                                        KeyCode::Up => (0, -step),
+                                        KeyCode::Down => (0, step),
+                                        KeyCode::Left => (-step, 0),
+                                        _ => (step, 0),
+                                    };
+                                    if state.nodes.iter().any(|n| n.selected) {
+                                        if state.nodes.iter().filter(|n| n.selected).all(|n| n.locked) {
+                                            status_msg = String::from("Shape is locked");
+                                            continue;
                                         }
+                                        // Frames carry their contents along unless Alt is held
+                                        let mut ids = Vec::new();
+                                        for node in state.nodes.iter().filter(|n| n.selected) {
+                                            ids.extend(state.group_members(node.id));
+                                            if node.shape == ShapeType::Frame && !key.modifiers.contains(KeyModifiers::ALT) {
+                                                ids.extend(state.nodes_in_frame(node.id));
+                                            }
+                                        }
+                                        ids.sort_unstable();
+                                        ids.dedup();
+                                        state.move_nodes(&ids, dx, dy);
+                                        state.edit();
                                     } else {
                                         // Pan the camera if no node is selected
-                                        match key.code {
-                                            KeyCode::Up => state.camera_offset.1 = state.camera_offset.1.saturating_sub(1),
-                                            KeyCode::Down => state.camera_offset.1 += 1,
-                                            KeyCode::Left => state.camera_offset.0 = state.camera_offset.0.saturating_sub(1),
-                                            KeyCode::Right => state.camera_offset.0 += 1,
-                                            _ => {}
-                                        }
+                                        state.camera_offset.0 += dx;
+                                        state.camera_offset.1 += dy;
                                         status_msg = format!("Canvas Pan: {}, {}", state.camera_offset.0, state.camera_offset.1);
                                     }
                                 }
+                                // Big jumps only ever pan; shapes stay put even when selected
+                                KeyCode::Home | KeyCode::End => {
+                                    if let Some((bx, by, bw, bh)) = state.bounds() {
+                                        state.camera_offset = if key.code == KeyCode::Home {
+                                            (bx, by)
+                                        } else {
+                                            // Bottom-right corner of the diagram in the bottom-right of the view
+                                            (
+                                                (bx + bw as i32 - inner_area_cache.width as i32).max(bx),
+                                                (by + bh as i32 - inner_area_cache.height as i32).max(by),
+                                            )
+                                        };
+                                        status_msg = format!("Canvas Pan: {}, {}", state.camera_offset.0, state.camera_offset.1);
+                                    }
+                                }
+                                KeyCode::PageUp | KeyCode::PageDown => {
+                                    let page = inner_area_cache.height.max(1) as i32;
+                                    state.camera_offset.1 += if key.code == KeyCode::PageUp { -page } else { page };
+                                    status_msg = format!("Canvas Pan: {}, {}", state.camera_offset.0, state.camera_offset.1);
+                                }
                                 _ => {}
                             }
                         }
                     }
                 }
+                Event::Paste(data) => {
+                    if let AppMode::Label(idx) = state.mode
+                        && let Some(conn) = state.connections.get_mut(idx) {
+                        // Labels are a single line
+                        model::append_label(&mut conn.label, &data.lines().collect::<Vec<_>>().join(" "), state.tab_width);
+                        status_msg = label_prompt(&conn.label);
+                        state.edit();
+                    }
+                    if let AppMode::Geometry(_) = state.mode {
+                        state.geometry_input.push_str(&data.lines().collect::<Vec<_>>().join(" "));
+                        status_msg = geometry_prompt(&state.geometry_input);
+                    }
+                    if let AppMode::Insert(id) = state.mode
+                        && let Some(node) = state.nodes.iter_mut().find(|n| n.id == id) {
+                        if state.template_pending.take() == Some(id) { node.text.clear(); }
+                        model::append_label(&mut node.text, &data, state.tab_width);
+                        node.fit_to_text();
+                        state.edit();
+                    }
+                }
                 Event::Mouse(mouse) => {
+                    // The unfocused pane only takes a click, which moves the focus there
+                    if let Some(split) = split.as_mut()
+                        && other_area_cache.contains(ratatui::layout::Position::new(mouse.column, mouse.row)) {
+                        if matches!(mouse.kind, event::MouseEventKind::Down(_)) {
+                            status_msg = switch_focus(&mut docs, &mut active, split);
+                        }
+                        continue;
+                    }
+                    // A palette entry arms its shape for the next canvas click
+                    if show_palette && palette_area_cache.contains(ratatui::layout::Position::new(mouse.column, mouse.row)) {
+                        if matches!(mouse.kind, event::MouseEventKind::Down(event::MouseButton::Left))
+                            && let Some(&(shape, _, name, _)) = PALETTE.get((mouse.row - palette_area_cache.y) as usize) {
+                            palette_armed = Some(shape);
+                            status_msg = format!("Click the canvas to place a {} (Esc cancels)", name);
+                        }
+                        continue;
+                    }
                     if mouse.column < inner_area_cache.x || mouse.row < inner_area_cache.y {
                         continue;
                     }
                     let mx_screen = mouse.column - inner_area_cache.x;
                     let my_screen = mouse.row - inner_area_cache.y;
                     
-                    let mx = (mx_screen as i32 + state.camera_offset.0).max(0) as u16;
-                    let my = (my_screen as i32 + state.camera_offset.1).max(0) as u16;
+                    let mx = mx_screen as i32 + state.camera_offset.0;
+                    let my = my_screen as i32 + state.camera_offset.1;
+                    state.hover_pos = Some((mx, my));
+                    // Hovering isn't drawn; clicks, drags and scrolls change the selection, a drag or the view
+                    if mouse.kind != event::MouseEventKind::Moved {
+                        state.touch();
+                    }
 
                     // --- CONTEXT MENU HANDLING ---
-                    if let AppMode::ContextMenu { x, y, .. } = state.mode {
-                        let width = 21;
-                        let height = 11; // items.len() + 2
+                    if let AppMode::ContextMenu { x, y, target, .. } = state.mode {
+                        let items = target.items();
+                        let width = CONTEXT_MENU_WIDTH;
+                        let height = items.len() as u16 + 2;
                         let screen_x = inner_area_cache.x + x;
                         let screen_y = inner_area_cache.y + y;
                         let menu_x = if screen_x + width > area.width { area.width.saturating_sub(width) } else { screen_x };
@@ -740,75 +2087,13 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
 
                         if mouse.column >= menu_x && mouse.column < menu_x + width &&
                            mouse.row >= menu_y && mouse.row < menu_y + height {
-                            let local_y = mouse.row.saturating_sub(menu_y).saturating_sub(1);
-                            if local_y < 10 && local_y != 4 && local_y != 8 {
-                                state.mode = AppMode::ContextMenu { x, y, selected_index: local_y as usize };
+                            let local_y = mouse.row.saturating_sub(menu_y).saturating_sub(1) as usize;
+                            if let Some(action) = items.get(local_y).and_then(|item| item.action()) {
+                                state.mode = AppMode::ContextMenu { x, y, selected_index: local_y, target };
                                 if matches!(mouse.kind, event::MouseEventKind::Down(event::MouseButton::Left)) {
-                                    let id = state.nodes.iter().map(|n| n.id).max().unwrap_or(0) + 1;
-                                    let world_x = (x as i32 + state.camera_offset.0).max(0) as u16;
-                                    let world_y = (y as i32 + state.camera_offset.1).max(0) as u16;
-                                    
-                                    match local_y {
-                                        0 => { // New Box
-                                            state.nodes.push(Node { id, shape: ShapeType::Box, x: world_x, y: world_y, width: 20, height: 5, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        1 => { // New Diamond
-                                            state.nodes.push(Node { id, shape: ShapeType::Diamond, x: world_x, y: world_y, width: 15, height: 7, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        2 => { // New Text
-                                            state.nodes.push(Node { id, shape: ShapeType::Text, x: world_x, y: world_y, width: 10, height: 1, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        3 => { // New Frame
-                                            state.nodes.push(Node { id, shape: ShapeType::Frame, x: world_x, y: world_y, width: 30, height: 10, text: String::new(), selected: true });
-                                            state.mode = AppMode::Insert(id);
-                                        }
-                                        5 => { // Start Connector
-                                            if let Some(node) = state.nodes.iter().rev().find(|n| n.contains(world_x, world_y)) {
-                                                state.connection_source_id = Some(node.id);
-                                                state.connection_has_arrow = false;
-                                                status_msg = format!("Connector source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
-                                            } else {
-                                                status_msg = String::from("No node at click position");
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        6 => { // Start Arrow
-                                            if let Some(node) = state.nodes.iter().rev().find(|n| n.contains(world_x, world_y)) {
-                                                state.connection_source_id = Some(node.id);
-                                                state.connection_has_arrow = true;
-                                                status_msg = format!("Arrow source: {}. Tab to target, Enter to finish.", node.text.split_whitespace().next().unwrap_or("Node"));
-                                            } else {
-                                                status_msg = String::from("No node at click position");
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        7 => { // Delete
-                                            if let Some(idx) = state.nodes.iter().position(|n| n.contains(world_x, world_y)) {
-                                                let node_id = state.nodes[idx].id;
-                                                state.nodes.remove(idx);
-                                                state.connections.retain(|c| c.from_id != node_id && c.to_id != node_id);
-                                                status_msg = String::from("Shape and connections deleted");
-                                            } else {
-                                                for (i, conn) in state.connections.iter().enumerate().rev() {
-                                                    if conn.contains(world_x, world_y, &state.nodes) {
-                                                        state.connections.remove(i);
-                                                        status_msg = String::from("Connection deleted");
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                            state.mode = AppMode::Normal;
-                                        }
-                                        9 => { state.mode = AppMode::Normal; }
-                                        _ => { state.mode = AppMode::Normal; }
-                                    }
-                                    if local_y < 4 {
-                                        for n in &mut state.nodes { if n.id != id { n.selected = false; } }
-                                        state.selected_connection_index = None;
-                                    }
+                                    let world_x = x as i32 + state.camera_offset.0;
+                                    let world_y = y as i32 + state.camera_offset.1;
+                                    status_msg = apply_menu_action(state, action, target, (world_x, world_y)).unwrap_or(status_msg);
                                     continue;
                                 }
                             }
@@ -821,116 +2106,253 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: AppState) -> io::R
                     }
 
                     if matches!(mouse.kind, event::MouseEventKind::Down(event::MouseButton::Right)) {
-                        state.mode = AppMode::ContextMenu { x: mx_screen, y: my_screen, selected_index: 0 };
+                        // Offer entries for whatever is under the cursor
+                        let target = state.target_at(mx, my);
+                        state.mode = AppMode::ContextMenu { x: mx_screen, y: my_screen, selected_index: 0, target };
                         continue;
                     }
                     // --- END CONTEXT MENU HANDLING ---
 
                     match mouse.kind {
+                        event::MouseEventKind::Down(event::MouseButton::Left) if palette_armed.is_some() => {
+                            if let Some(shape) = palette_armed.take() {
+                                apply_menu_action(state, MenuAction::NewShape(shape), MenuTarget::Canvas, (mx, my));
+                                status_msg = format!("{:?} placed; type its text (Esc to finish)", shape);
+                            }
+                        }
+                        event::MouseEventKind::Down(event::MouseButton::Left) if state.click_connect => {
+                            let Some(node) = state.node_index_at(mx, my).map(|i| &state.nodes[i]) else {
+                                status_msg = String::from("Click a shape to link (Esc cancels)");
+                                continue;
+                            };
+                            let name = node.text.split_whitespace().next().unwrap_or("Node").to_string();
+                            match state.connection_source_id.and_then(|id| state.nodes.iter().find(|n| n.id == id)) {
+                                Some(src) if src.id != node.id => {
+                                    // A source picked by keyboard has no clicked anchor; face the target instead
+                                    let (facing_from, _) = model::facing_anchors(src, node);
+                                    let from_offset = state.connection_source_offset.unwrap_or(facing_from);
+                                    let to_offset = {
+                                        let (x, y) = src.point_at(from_offset);
+                                        node.anchor_toward(x, y)
+                                    };
+                                    let (from_offset, to_offset) = state.link_anchors(src, from_offset, node, to_offset);
+                                    let connection = model::Connection {
+                                        from_id: src.id,
+                                        from_offset,
+                                        to: Endpoint::NodeAnchor { to_id: node.id, to_offset },
+                                        has_arrow: state.connection_has_arrow,
+                                        weight: Weight::Thin,
+                                        bend: Bend::Middle,
+                                        bidirectional: false,
+                                        label: String::new(),
+                                    };
+                                    state.connections.push(connection);
+                                    state.connection_source_id = None;
+                                    state.connection_source_offset = None;
+                                    state.click_connect = false;
+                                    state.edit();
+                                    status_msg = format!("Linked to {}", name);
+                                }
+                                _ => {
+                                    state.connection_source_id = Some(node.id);
+                                    state.connection_source_offset = Some(node.anchor_nearest(mx, my));
+                                    status_msg = format!("Link source: {}. Click the target (Esc cancels)", name);
+                                }
+                            }
+                        }
                         event::MouseEventKind::Down(event::MouseButton::Left) => {
                             state.dragging_node_id = None;
                             state.resizing_node_id = None;
                             state.partial_connection = None;
+                            state.drag_group.clear();
+                            // Clicking anywhere finishes a label being typed, or drops a placement
+                            if matches!(state.mode, AppMode::Label(_) | AppMode::Geometry(_)) {
+                                state.mode = AppMode::Normal;
+                            }
                             
                             let mut hit_node_id = None;
-                            let mut is_border = false;
-                            let mut is_corner = false;
+                            let mut hit_locked = false;
+                            let mut handle = model::ResizeHandle::default();
                             let mut node_offset = (0, 0);
+                            let mut on_title = false;
+                            let mut hit_frame = false;
+                            let mut hit_resizable = false;
 
-                            for node in state.nodes.iter().rev() {
-                                if node.contains(mx, my) {
-                                    hit_node_id = Some(node.id);
-                                    node_offset = (mx - node.x, my - node.y);
-                                    if mx == node.x + node.width - 1 && my == node.y + node.height - 1 {
-                                        is_corner = true;
-                                    } else if mx == node.x || mx == node.x + node.width - 1 || 
-                                              my == node.y || my == node.y + node.height - 1 {
-                                        is_border = true;
-                                    }
-                                    break;
-                                }
+                            if let Some(node) = state.node_at(mx, my) {
+                                hit_node_id = Some(node.id);
+                                hit_locked = node.locked;
+                                hit_resizable = node.shape.resizable();
+                                node_offset = ((mx - node.x) as u16, (my - node.y) as u16);
+                                handle = model::ResizeHandle::at(node, mx, my);
+                                hit_frame = node.shape == ShapeType::Frame;
+                                // A frame's title picks the frame up instead of starting a link from its border
+                                on_title = hit_frame && node_offset.1 == 0 && renderer::frame_title(node)
+                                    .is_some_and(|title| (2..2 + model::display_width(&title)).contains(&node_offset.0));
                             }
 
+                            // Corners always resize; edges resize with Ctrl and otherwise start a connection
+                            let edge_resize = handle.is_edge() && mouse.modifiers.contains(KeyModifiers::CONTROL);
                             if let Some(id) = hit_node_id {
-                                if is_corner {
+                                if (handle.is_corner() || edge_resize) && !hit_locked && hit_resizable {
                                     state.resizing_node_id = Some(id);
-                                } else if is_border {
+                                    state.resize_handle = handle;
+                                } else if handle.is_edge() && !on_title {
                                     if let Some(node) = state.nodes.iter().find(|n| n.id == id) {
                                         let snapped_offset = if node_offset.1 == 0 { (node.width / 2, 0) }
                                             else if node_offset.1 == node.height - 1 { (node.width / 2, node.height - 1) }
                                             else if node_offset.0 == 0 { (0, node.height / 2) }
                                             else { (node.width - 1, node.height / 2) };
 
-                                        state.partial_connection = Some(crate::model::PartialConnection::Starting {
+                                        state.partial_connection = Some(model::PartialConnection::Starting {
                                             from_id: id,
                                             from_offset: snapped_offset,
                                             current_pos: (mx, my),
                                         });
                                     }
+                                } else if hit_locked {
+                                    // Locked shapes can be selected but not picked up
+                                    for n in &mut state.nodes { n.selected = n.id == id; }
+                                    state.selected_connection_index = None;
+                                    status_msg = String::from("Shape is locked");
                                 } else {
+                                    // Clicking away from the shape being edited stops editing it
+                                    if matches!(state.mode, AppMode::Insert(editing) if editing != id) {
+                                        state.mode = AppMode::Normal;
+                                    }
                                     state.dragging_node_id = Some(id);
                                     state.drag_offset = node_offset;
+                                    state.drag_start = Some((mx, my));
+                                    // Capture the frame's contents now so nodes don't escape mid-drag
+                                    if !mouse.modifiers.contains(KeyModifiers::ALT) {
+                                        state.drag_group = state.nodes_in_frame(id);
+                                    }
+                                    // Shift+click on a frame also selects everything inside it
+                                    let grab_contents = hit_frame && mouse.modifiers.contains(KeyModifiers::SHIFT)
+                                        && !mouse.modifiers.contains(KeyModifiers::ALT);
+                                    if grab_contents {
+                                        status_msg = format!("Frame and {} shape(s) inside selected", state.drag_group.len());
+                                    }
+                                    // Clicking any group member picks up the whole group
+                                    let members = state.group_members(id);
+                                    state.drag_group.extend(members.iter().filter(|&&m| m != id));
                                     if let Some(idx) = state.nodes.iter().position(|n| n.id == id) {
-                                        for n in &mut state.nodes { n.selected = false; }
-                                        state.nodes[idx].selected = true;
-                                        let node = state.nodes.remove(idx);
-                                        state.nodes.push(node);
+                                        let contents = if grab_contents { state.drag_group.clone() } else { Vec::new() };
+                                        for n in &mut state.nodes { n.selected = members.contains(&n.id) || contents.contains(&n.id); }
+                                        // Raising the shape to the top changes the saved drawing order
+                                        if idx + 1 != state.nodes.len() {
+                                            let node = state.nodes.remove(idx);
+                                            state.nodes.push(node);
+                                            state.edit();
+                                        }
                                     }
                                 }
                             } else {
                                 state.mode = AppMode::Normal;
                                 state.selected_connection_index = None;
                                 for n in &mut state.nodes { n.selected = false; }
-                                for (i, conn) in state.connections.iter().enumerate().rev() {
-                                    if conn.contains(mx, my, &state.nodes) {
-                                        state.selected_connection_index = Some(i);
-                                        status_msg = String::from("Connection selected | 'a': Arrow | 'Del': Remove");
-                                        break;
-                                    }
+                                if let Some(i) = state.connection_near(mx, my) {
+                                    state.selected_connection_index = Some(i);
+                                    status_msg = String::from("Connection selected | 'a': Arrow | 'Del': Remove");
                                 }
+                                // Dragging from empty canvas pans the camera, or draws a selection band with Ctrl
+                                if state.selected_connection_index.is_none() && mouse.modifiers.contains(KeyModifiers::CONTROL) {
+                                    state.rubber_band = Some(((mx, my), (mx, my)));
+                                } else if state.selected_connection_index.is_none() {
+                                    state.pan_start = Some((mx_screen, my_screen));
+                                    state.pan_last = (mx_screen, my_screen);
+                                }
+                            }
+                        }
+                        event::MouseEventKind::ScrollUp | event::MouseEventKind::ScrollDown
+                        | event::MouseEventKind::ScrollLeft | event::MouseEventKind::ScrollRight
+                            if !matches!(state.mode, AppMode::ContextMenu { .. }) => {
+                            // Wheel pans vertically; Shift+wheel (or a horizontal wheel) pans sideways
+                            let step = model::SCROLL_STEP as i32;
+                            let delta = match mouse.kind {
+                                event::MouseEventKind::ScrollUp | event::MouseEventKind::ScrollLeft => -step,
+                                _ => step,
+                            };
+                            let horizontal = mouse.modifiers.contains(KeyModifiers::SHIFT)
+                                || matches!(mouse.kind, event::MouseEventKind::ScrollLeft | event::MouseEventKind::ScrollRight);
+                            if horizontal {
+                                state.camera_offset.0 += delta;
+                            } else {
+                                state.camera_offset.1 += delta;
+                            }
+                            status_msg = format!("Canvas Pan: {}, {}", state.camera_offset.0, state.camera_offset.1);
+                        }
+                        event::MouseEventKind::Down(event::MouseButton::Middle) => {
+                            state.pan_start = Some((mx_screen, my_screen));
+                            state.pan_last = (mx_screen, my_screen);
+                        }
+                        event::MouseEventKind::Drag(event::MouseButton::Left) | event::MouseEventKind::Drag(event::MouseButton::Middle) if state.pan_start.is_some() => {
+                            state.camera_offset.0 -= mx_screen as i32 - state.pan_last.0 as i32;
+                            state.camera_offset.1 -= my_screen as i32 - state.pan_last.1 as i32;
+                            state.pan_last = (mx_screen, my_screen);
+                            status_msg = format!("Canvas Pan: {}, {}", state.camera_offset.0, state.camera_offset.1);
+                        }
+                        event::MouseEventKind::Up(event::MouseButton::Middle) => {
+                            state.pan_start = None;
+                        }
+                        event::MouseEventKind::Drag(event::MouseButton::Left) if state.rubber_band.is_some() => {
+                            if let Some((_, current)) = &mut state.rubber_band {
+                                *current = (mx, my);
                             }
                         }
                         event::MouseEventKind::Drag(event::MouseButton::Left) => {
                             if let Some(pc) = &mut state.partial_connection {
-                                match pc { crate::model::PartialConnection::Starting { current_pos, .. } => { *current_pos = (mx, my); } }
+                                match pc { model::PartialConnection::Starting { current_pos, .. } => { *current_pos = (mx, my); } }
                             } else if let Some(id) = state.resizing_node_id {
                                 if let Some(node) = state.nodes.iter_mut().find(|n| n.id == id) {
-                                    node.width = (mx.saturating_sub(node.x) + 1).max(3);
-                                    node.height = (my.saturating_sub(node.y) + 1).max(3);
-                                }
-                            } else if let Some(id) = state.dragging_node_id {
-                                if let Some(node) = state.nodes.iter_mut().find(|n| n.id == id) {
-                                    node.x = mx.saturating_sub(state.drag_offset.0);
-                                    node.y = my.saturating_sub(state.drag_offset.1);
-                                    node.x = node.x.min(inner_area_cache.width.saturating_sub(node.width));
-                                    node.y = node.y.min(inner_area_cache.height.saturating_sub(node.height));
+                                    state.resize_handle.apply(node, mx, my);
+                                    state.edit();
                                 }
+                            } else if let Some(id) = state.dragging_node_id
+                                && let Some(node) = state.nodes.iter().find(|n| n.id == id) {
+                                let new_x = mx - state.drag_offset.0 as i32;
+                                let new_y = my - state.drag_offset.1 as i32;
+                                let ((new_x, new_y), guides) = state.snap_position(id, &state.drag_group, new_x, new_y);
+                                state.snap_guides = guides;
+                                let (dx, dy) = (new_x - node.x, new_y - node.y);
+                                let mut ids = state.drag_group.clone();
+                                ids.push(id);
+                                state.move_nodes(&ids, dx, dy);
+                                if (dx, dy) != (0, 0) { state.edit(); }
                             }
                         }
                         event::MouseEventKind::Up(event::MouseButton::Left) => {
-                            if let Some(crate::model::PartialConnection::Starting { from_id, from_offset, .. }) = state.partial_connection {
-                                for node in &state.nodes {
-                                    if node.id != from_id && node.contains(mx, my) {
-                                        let dx_left = mx.saturating_sub(node.x);
-                                        let dx_right = (node.x + node.width - 1).saturating_sub(mx);
-                                        let dy_top = my.saturating_sub(node.y);
-                                        let dy_bottom = (node.y + node.height - 1).saturating_sub(my);
-                                        let min_dist = dx_left.min(dx_right).min(dy_top).min(dy_bottom);
-                                        let to_offset = if min_dist == dy_top { (node.width / 2, 0) }
-                                            else if min_dist == dy_bottom { (node.width / 2, node.height - 1) }
-                                            else if min_dist == dx_left { (0, node.height / 2) }
-                                            else { (node.width - 1, node.height / 2) };
-
-                                        state.connections.push(crate::model::Connection { from_id, from_offset, to_id: node.id, to_offset, has_arrow: true });
-                                        break;
-                                    }
+                            if let Some((start, current)) = state.rubber_band.take() {
+                                for n in &mut state.nodes { n.selected = n.intersects(start, current); }
+                                let count = state.nodes.iter().filter(|n| n.selected).count();
+                                status_msg = format!("{} shapes selected", count);
+                            } else if let Some(model::PartialConnection::Starting { from_id, from_offset, .. }) = state.partial_connection {
+                                let landed = state.drop_link(from_id, from_offset, mx, my).is_some();
+                                if landed { state.edit(); }
+                                // Released over empty canvas: point the arrow at that spot
+                                if !landed && state.node_at(mx, my).is_none() {
+                                    state.connections.push(model::Connection { from_id, from_offset, to: Endpoint::FreePoint { to_point: (mx, my) }, has_arrow: true, weight: Weight::Thin, bend: Bend::Middle, bidirectional: false, label: String::new() });
+                                    state.edit();
+                                    status_msg = String::from("Connection to point created");
+                                }
+                            } else if let Some(id) = state.dragging_node_id
+                                && state.drag_start == Some((mx, my)) {
+                                // A click only selects; a second one on the same shape soon after edits it
+                                let double = state.last_click
+                                    .is_some_and(|(at, last)| last == id && at.elapsed().as_millis() <= model::DOUBLE_CLICK_MS);
+                                if double {
+                                    state.mode = AppMode::Insert(id);
+                                    state.last_click = None;
+                                } else {
+                                    state.last_click = Some((std::time::Instant::now(), id));
                                 }
-                            } else if let Some(id) = state.dragging_node_id {
-                                state.mode = AppMode::Insert(id);
                             }
                             state.dragging_node_id = None;
+                            state.drag_start = None;
                             state.resizing_node_id = None;
                             state.partial_connection = None;
+                            state.drag_group.clear();
+                            state.snap_guides = (None, None);
+                            state.pan_start = None;
                         }
                         _ => {}
                     }
@@ -1,6 +1,13 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Instant, SystemTime};
+
 use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub type Point = (i32, i32); // (col, row), world cells; may be negative
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum ShapeType {
     Box,      // Rectangular
     Diamond,  // Decision
@@ -8,21 +15,332 @@ pub enum ShapeType {
     Frame,    // Grouping frame with title
 }
 
+impl ShapeType {
+    /// Width and height a freshly created shape starts out with.
+    pub fn default_size(&self) -> (u16, u16) {
+        match self {
+            ShapeType::Box => (20, 5),
+            ShapeType::Diamond => (15, 7),
+            ShapeType::Text => (10, 1),
+            ShapeType::Frame => (30, 10),
+        }
+    }
+
+    /// Smallest width and height the shape still draws sensibly at.
+    pub fn min_size(&self) -> (u16, u16) {
+        match self {
+            ShapeType::Box | ShapeType::Frame => (MIN_RESIZE, MIN_RESIZE),
+            ShapeType::Diamond => (5, 3),
+            ShapeType::Text => (1, 1),
+        }
+    }
+
+    /// Whether the user can set the size by hand. Text shapes always fit their label
+    /// (`Node::fit_to_text`), so a manual size would be lost on the next keystroke.
+    pub fn resizable(&self) -> bool {
+        *self != ShapeType::Text
+    }
+
+    /// The next shape when cycling a node's type.
+    pub fn next(self) -> Self {
+        match self {
+            ShapeType::Box => ShapeType::Diamond,
+            ShapeType::Diamond => ShapeType::Text,
+            ShapeType::Text => ShapeType::Frame,
+            ShapeType::Frame => ShapeType::Box,
+        }
+    }
+}
+
+/// A shape on the canvas. Its position is in world cells, which run in every direction from the
+/// origin, so shapes may sit above or left of it. Files from when positions were unsigned load
+/// as they are, since every old value is a valid new one.
+///
+/// ```
+/// use dxgmr::model::{AppState, Node, ShapeType};
+/// use dxgmr::renderer::render_region;
+///
+/// let old: Node = serde_json::from_str(
+///     r#"{"id":1,"shape":"Box","x":65535,"y":3,"width":9,"height":3,"text":"Far","selected":false}"#,
+/// ).unwrap();
+/// assert_eq!((old.x, old.y), (65535, 3));
+///
+/// // Straddling the origin: the part left of the camera is clipped, not squashed
+/// let mut state = AppState::new(String::from("Negative"));
+/// state.add_node(ShapeType::Box, -4, -1, "Left");
+/// let canvas = render_region(&state, (0, 0), 16, 2);
+/// assert_eq!(canvas.grid[1].iter().collect::<String>(), "    Left       |");
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
     pub id: usize,
     pub shape: ShapeType,
-    pub x: u16,      // Col
-    pub y: u16,      // Row
+    pub x: i32,      // Col
+    pub y: i32,      // Row
     pub width: u16,
     pub height: u16,
     pub text: String,
     pub selected: bool,
+    #[serde(default)]
+    pub locked: bool, // Excluded from moving, resizing, deleting and editing
+    #[serde(default)]
+    pub group: Option<usize>, // Nodes sharing a group id select and move as one
+    #[serde(default)]
+    pub style: NodeStyle,
+    #[serde(default)]
+    pub shadow: bool, // Boxes only: shade one cell below-right
+}
+
+/// Border look of boxes and frames.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum NodeStyle {
+    #[default]
+    Plain,   // +--+
+    Rounded, // .--.
+    Double,  // #==#
+}
+
+impl NodeStyle {
+    pub fn next(self) -> Self {
+        match self {
+            NodeStyle::Plain => NodeStyle::Rounded,
+            NodeStyle::Rounded => NodeStyle::Double,
+            NodeStyle::Double => NodeStyle::Plain,
+        }
+    }
+}
+
+/// Everything about a node's look, without its text or place: what `AppState::copy_style`
+/// yanks and `AppState::paste_style` puts on other nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StyleSnapshot {
+    pub shape: ShapeType,
+    pub style: NodeStyle,
+    pub shadow: bool,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl StyleSnapshot {
+    pub fn of(node: &Node) -> Self {
+        Self { shape: node.shape, style: node.style, shadow: node.shadow, width: node.width, height: node.height }
+    }
 }
 
 impl Node {
-    pub fn contains(&self, x: u16, y: u16) -> bool {
-        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width as i32 && y >= self.y && y < self.y + self.height as i32
+    }
+
+    /// World cell of an offset relative to the top-left corner.
+    pub fn point_at(&self, offset: (u16, u16)) -> Point {
+        (self.x + offset.0 as i32, self.y + offset.1 as i32)
+    }
+
+    /// Text nodes have no border, so their box tracks the label exactly. Trailing spaces don't
+    /// count towards the width, and an empty label keeps a one-cell box so it stays clickable.
+    pub fn fit_to_text(&mut self) {
+        if self.shape == ShapeType::Text {
+            let lines: Vec<&str> = self.text.split('\n').collect();
+            self.width = lines.iter().map(|l| display_width(l.trim_end())).max().unwrap_or(0).max(1);
+            self.height = lines.len() as u16;
+        }
+    }
+
+    /// Offset of the border midpoint on the side closest to the point.
+    pub fn anchor_nearest(&self, x: i32, y: i32) -> (u16, u16) {
+        let dx_left = (x - self.x).max(0);
+        let dx_right = (self.x + self.width as i32 - 1 - x).max(0);
+        let dy_top = (y - self.y).max(0);
+        let dy_bottom = (self.y + self.height as i32 - 1 - y).max(0);
+        let min_dist = dx_left.min(dx_right).min(dy_top).min(dy_bottom);
+        if min_dist == dy_top { (self.width / 2, 0) }
+        else if min_dist == dy_bottom { (self.width / 2, self.height.saturating_sub(1)) }
+        else if min_dist == dx_left { (0, self.height / 2) }
+        else { (self.width.saturating_sub(1), self.height / 2) }
+    }
+
+    /// Moves an anchor taken at an older size `(old_w, old_h)` to the middle of the same side at
+    /// the current size. Anchors that weren't on the border are just clamped inside.
+    pub fn reanchor(&self, offset: (u16, u16), old_w: u16, old_h: u16) -> (u16, u16) {
+        if offset.1 == 0 { (self.width / 2, 0) }
+        else if offset.1 >= old_h.saturating_sub(1) { (self.width / 2, self.height.saturating_sub(1)) }
+        else if offset.0 == 0 { (0, self.height / 2) }
+        else if offset.0 >= old_w.saturating_sub(1) { (self.width.saturating_sub(1), self.height / 2) }
+        else { (offset.0.min(self.width.saturating_sub(1)), offset.1.min(self.height.saturating_sub(1))) }
+    }
+
+    /// An anchor offset pulled back inside the node, for offsets that outgrew it (hand-edited
+    /// files, or a shape shrunk since the link was made).
+    pub fn clamp_offset(&self, offset: (u16, u16)) -> (u16, u16) {
+        (offset.0.min(self.width.saturating_sub(1)), offset.1.min(self.height.saturating_sub(1)))
+    }
+
+    pub fn center(&self) -> Point {
+        self.point_at((self.width / 2, self.height / 2))
+    }
+
+    /// Offset of the border midpoint on the side facing the point, judged by the angle from
+    /// the center. Rows are about twice as tall as columns, so vertical distance counts double.
+    pub fn anchor_toward(&self, x: i32, y: i32) -> (u16, u16) {
+        let (cx, cy) = self.center();
+        let dx = x - cx;
+        let dy = (y - cy) * 2;
+        if dy.abs() >= dx.abs() && dy != 0 {
+            if dy > 0 { (self.width / 2, self.height.saturating_sub(1)) } else { (self.width / 2, 0) }
+        } else if dx >= 0 {
+            (self.width.saturating_sub(1), self.height / 2)
+        } else {
+            (0, self.height / 2)
+        }
+    }
+
+    /// Whether the node's bounding box overlaps the rectangle spanned by two corner points.
+    pub fn intersects(&self, a: Point, b: Point) -> bool {
+        let (left, right) = (a.0.min(b.0), a.0.max(b.0));
+        let (top, bottom) = (a.1.min(b.1), a.1.max(b.1));
+        self.x <= right && self.x + self.width as i32 > left && self.y <= bottom && self.y + self.height as i32 > top
+    }
+
+    /// Whether `other` lies fully inside this node's interior, clear of its border.
+    pub fn interior_holds(&self, other: &Node) -> bool {
+        other.x > self.x && other.y > self.y
+            && other.x + (other.width as i32) < self.x + self.width as i32
+            && other.y + (other.height as i32) < self.y + self.height as i32
+    }
+}
+
+/// Where a connection ends. Serialized flat into the connection so files written before
+/// free endpoints existed (plain `to_id`/`to_offset`) still load.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Endpoint {
+    NodeAnchor { to_id: usize, to_offset: (u16, u16) }, // Offset relative to node top-left
+    FreePoint { to_point: Point },                      // Absolute world coords
+}
+
+impl Endpoint {
+    pub fn node_id(&self) -> Option<usize> {
+        match self {
+            Endpoint::NodeAnchor { to_id, .. } => Some(*to_id),
+            Endpoint::FreePoint { .. } => None,
+        }
+    }
+}
+
+/// Anchors for linking two nodes: each side faces the other node's center.
+pub fn facing_anchors(from: &Node, to: &Node) -> ((u16, u16), (u16, u16)) {
+    let (fx, fy) = from.center();
+    let (tx, ty) = to.center();
+    (from.anchor_toward(tx, ty), to.anchor_toward(fx, fy))
+}
+
+/// Pulls the anchors of a new link onto one row (side-by-side shapes) or column (stacked ones)
+/// when they are a single cell off, so the route is a straight line rather than a one-cell jog.
+/// The target end moves if the source's row / column crosses its side, else the source end.
+pub fn align_anchors(from: &Node, from_offset: (u16, u16), to: &Node, to_offset: (u16, u16)) -> ((u16, u16), (u16, u16)) {
+    let on_top_or_bottom = |n: &Node, (_, oy): (u16, u16)| oy == 0 || oy + 1 == n.height;
+    let interior = |start: i32, len: u16, v: i32| v > start && v + 1 < start + len as i32;
+    let (fx, fy) = from.point_at(from_offset);
+    let (tx, ty) = to.point_at(to_offset);
+    let (mut from_offset, mut to_offset) = (from_offset, to_offset);
+    match (on_top_or_bottom(from, from_offset), on_top_or_bottom(to, to_offset)) {
+        (false, false) if fy.abs_diff(ty) == 1 => {
+            if interior(to.y, to.height, fy) {
+                to_offset.1 = (fy - to.y) as u16;
+            } else if interior(from.y, from.height, ty) {
+                from_offset.1 = (ty - from.y) as u16;
+            }
+        }
+        (true, true) if fx.abs_diff(tx) == 1 => {
+            if interior(to.x, to.width, fx) {
+                to_offset.0 = (fx - to.x) as u16;
+            } else if interior(from.x, from.width, tx) {
+                from_offset.0 = (tx - from.x) as u16;
+            }
+        }
+        _ => {}
+    }
+    (from_offset, to_offset)
+}
+
+/// Line weight of a connection; thick lines mark the primary flow.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum Weight {
+    #[default]
+    Thin,
+    Thick,
+}
+
+impl Weight {
+    pub fn next(self) -> Self {
+        match self {
+            Weight::Thin => Weight::Thick,
+            Weight::Thick => Weight::Thin,
+        }
+    }
+}
+
+/// Where the middle leg of a Z- or S-route runs: halfway between the ends, hugging one of them,
+/// or a set share of the way along. Being relative to the ends, it stays put in proportion
+/// while the shapes move instead of the route being laid out afresh.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Bend {
+    #[default]
+    Middle,
+    Source,
+    Target,
+    At(u8), // Percent of the way from the source end to the target end
+}
+
+impl Bend {
+    pub fn next(self) -> Self {
+        match self {
+            Bend::Middle => Bend::Source,
+            Bend::Source => Bend::Target,
+            Bend::Target | Bend::At(_) => Bend::Middle,
+        }
+    }
+
+    /// Share of the way from the source the leg sits at, in percent.
+    pub fn percent(self) -> u8 {
+        match self {
+            Bend::Middle => 50,
+            Bend::Source => 0,
+            Bend::Target => 100,
+            Bend::At(p) => p.min(100),
+        }
+    }
+
+    /// The bend moved `delta` percent towards the target, stopping at either end.
+    pub fn shifted(self, delta: i8) -> Self {
+        Bend::At((self.percent() as i16 + delta as i16).clamp(0, 100) as u8)
+    }
+
+    pub fn describe(self) -> String {
+        match self {
+            Bend::Middle => String::from("Bend: halfway"),
+            Bend::Source => String::from("Bend: next to the source"),
+            Bend::Target => String::from("Bend: next to the target"),
+            Bend::At(_) => format!("Bend: {}% of the way from the source", self.percent()),
+        }
+    }
+
+    /// Where the middle leg goes between `from` and `to` along the axis it crosses. Hugging an
+    /// end keeps one cell clear of it so the leg doesn't run along that shape's border.
+    fn leg(self, from: i32, to: i32) -> i32 {
+        let toward = |a: i32, b: i32| if b > a { a + 1 } else { a - 1 };
+        match self {
+            _ if from.abs_diff(to) < 2 => (from + to).div_euclid(2),
+            Bend::Middle => (from + to).div_euclid(2),
+            Bend::Source => toward(from, to),
+            Bend::Target => toward(to, from),
+            Bend::At(_) => {
+                let at = from + (to - from) * self.percent() as i32 / 100;
+                let (near, far) = (toward(from, to), toward(to, from));
+                at.clamp(near.min(far), near.max(far))
+            }
+        }
     }
 }
 
@@ -30,43 +348,363 @@ impl Node {
 pub struct Connection {
     pub from_id: usize,
     pub from_offset: (u16, u16), // Relative to node top-left
-    pub to_id: usize,
-    pub to_offset: (u16, u16),   // Relative to node top-left
+    #[serde(flatten)]
+    pub to: Endpoint,
     pub has_arrow: bool,
+    #[serde(default)]
+    pub weight: Weight,
+    #[serde(default)]
+    pub bend: Bend,
+    #[serde(default)]
+    pub bidirectional: bool, // With has_arrow, also put an arrowhead at the source end
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String, // One line of text drawn over the route (`label_spot`); clicks still hit the route
 }
 
 impl Connection {
-    pub fn contains(&self, mx: u16, my: u16, nodes: &[Node]) -> bool {
-        let from = nodes.iter().find(|n| n.id == self.from_id);
-        let to = nodes.iter().find(|n| n.id == self.to_id);
+    /// Whether either end of the connection is attached to the given node.
+    pub fn touches(&self, node_id: usize) -> bool {
+        self.from_id == node_id || self.to.node_id() == Some(node_id)
+    }
+
+    /// Swaps the two ends so an arrow points the other way. A link to a free point has
+    /// no node to start from, so it is left alone and false is returned.
+    pub fn reverse(&mut self) -> bool {
+        let Endpoint::NodeAnchor { to_id, to_offset } = &mut self.to else { return false; };
+        std::mem::swap(&mut self.from_id, to_id);
+        std::mem::swap(&mut self.from_offset, to_offset);
+        true
+    }
+
+    /// Steps the arrowheads: none, at the target, at both ends, and back to none.
+    pub fn cycle_arrows(&mut self) -> &'static str {
+        match (self.has_arrow, self.bidirectional) {
+            (false, _) => {
+                self.has_arrow = true;
+                self.bidirectional = false;
+                "Arrow enabled"
+            }
+            (true, false) => {
+                self.bidirectional = true;
+                "Arrows at both ends"
+            }
+            (true, true) => {
+                self.has_arrow = false;
+                self.bidirectional = false;
+                "Arrow disabled"
+            }
+        }
+    }
+
+    /// Whether the source end gets an arrowhead too.
+    pub fn arrow_at_start(&self) -> bool {
+        self.has_arrow && self.bidirectional
+    }
+
+    /// Re-anchors both ends so the route is one straight line, when the shapes overlap enough
+    /// for a shared column (one above the other) or row (side by side) to exist. Returns false if
+    /// there is no such line, or the target is a free point.
+    pub fn straighten(&mut self, nodes: &[Node]) -> bool {
+        let Endpoint::NodeAnchor { to_id, to_offset } = &mut self.to else { return false; };
+        let (Some(f), Some(t)) = (nodes.iter().find(|n| n.id == self.from_id), nodes.iter().find(|n| n.id == *to_id)) else {
+            return false;
+        };
+        // Anchors stay off the corners, so only interior columns / rows can be shared
+        let shared = |a: i32, a_len: u16, b: i32, b_len: u16| {
+            let lo = (a + 1).max(b + 1);
+            let hi = (a + a_len as i32 - 2).min(b + b_len as i32 - 2);
+            (lo <= hi).then_some((lo + hi).div_euclid(2))
+        };
+        let f_above = f.y + f.height as i32 <= t.y;
+        let t_above = t.y + t.height as i32 <= f.y;
+        let f_left = f.x + f.width as i32 <= t.x;
+        let t_left = t.x + t.width as i32 <= f.x;
+        if (f_above || t_above) && let Some(col) = shared(f.x, f.width, t.x, t.width) {
+            let (from_row, to_row) = if f_above { (f.height.saturating_sub(1), 0) } else { (0, t.height.saturating_sub(1)) };
+            self.from_offset = ((col - f.x) as u16, from_row);
+            *to_offset = ((col - t.x) as u16, to_row);
+            true
+        } else if (f_left || t_left) && let Some(row) = shared(f.y, f.height, t.y, t.height) {
+            let (from_col, to_col) = if f_left { (f.width.saturating_sub(1), 0) } else { (0, t.width.saturating_sub(1)) };
+            self.from_offset = (from_col, (row - f.y) as u16);
+            *to_offset = (to_col, (row - t.y) as u16);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the route is a single straight line.
+    pub fn is_straight(&self, nodes: &[Node]) -> bool {
+        self.endpoints(nodes).is_some_and(|((x1, y1), (x2, y2), _, _)| x1 == x2 || y1 == y2)
+    }
+
+    /// Absolute start and end points, and whether the route leaves the source / enters the target
+    /// through a top or bottom side. An end with an arrowhead sits one cell outside its border
+    /// so the arrowhead doesn't cover it.
+    fn endpoints(&self, nodes: &[Node]) -> Option<(Point, Point, bool, bool)> {
+        let f = nodes.iter().find(|n| n.id == self.from_id)?;
+        let from_offset = f.clamp_offset(self.from_offset);
+        let mut start = f.point_at(from_offset);
+        let from_vertical = from_offset.1 == 0 || from_offset.1 == f.height.saturating_sub(1);
+        if self.arrow_at_start() {
+            start = step_outside(f, from_offset, start, nodes);
+        }
+        match self.to {
+            Endpoint::NodeAnchor { to_id, to_offset } => {
+                let t = nodes.iter().find(|n| n.id == to_id)?;
+                let to_offset = t.clamp_offset(to_offset);
+                let mut end = t.point_at(to_offset);
+                let to_vertical = to_offset.1 == 0 || to_offset.1 == t.height.saturating_sub(1);
+                if self.has_arrow {
+                    end = step_outside(t, to_offset, end, nodes);
+                }
+                Some((start, end, from_vertical, to_vertical))
+            }
+            // A free point has no side; mirror the exit so the route keeps its old Z/S shape
+            Endpoint::FreePoint { to_point } => Some((start, to_point, from_vertical, from_vertical)),
+        }
+    }
+
+    /// The segments the connection is drawn with. `None` if an endpoint node is missing.
+    pub fn route(&self, nodes: &[Node]) -> Option<Vec<Segment>> {
+        let (start, end, from_vertical, to_vertical) = self.endpoints(nodes)?;
+        Some(route_segments(start, end, from_vertical, to_vertical, self.bend))
+    }
+
+    pub fn contains(&self, mx: i32, my: i32, nodes: &[Node]) -> bool {
+        self.distance_to(mx, my, nodes) == Some(0)
+    }
+
+    /// Cells from the point to the nearest cell of the route, counting diagonal steps as one.
+    /// `None` if an endpoint node is missing.
+    pub fn distance_to(&self, mx: i32, my: i32, nodes: &[Node]) -> Option<u32> {
+        let segments = self.route(nodes)?;
+        segments.iter().map(|&((x1, y1), (x2, y2))| {
+            let dx = (x1.min(x2) - mx).max(mx - x1.max(x2)).max(0) as u32;
+            let dy = (y1.min(y2) - my).max(my - y1.max(y2)).max(0) as u32;
+            dx.max(dy)
+        }).min()
+    }
+}
+
+/// `point`, the anchor at `offset` on `node`, moved one cell off the side it sits on. Stays on
+/// the border when that cell belongs to another shape (frames aside, which are mostly empty).
+fn step_outside(node: &Node, offset: (u16, u16), point: Point, nodes: &[Node]) -> Point {
+    let (x, y) = point;
+    let outside = if offset.1 == 0 {
+        (x, y - 1)
+    } else if offset.1 == node.height.saturating_sub(1) {
+        (x, y + 1)
+    } else if offset.0 == 0 {
+        (x - 1, y)
+    } else if offset.0 == node.width.saturating_sub(1) {
+        (x + 1, y)
+    } else {
+        (x, y)
+    };
+    let blocked = nodes.iter()
+        .any(|n| n.id != node.id && n.shape != ShapeType::Frame && n.contains(outside.0, outside.1));
+    if blocked { point } else { outside }
+}
+
+/// Largest gap, in cells along each axis, between route ends that still gets a single-bend L
+/// in place of a Z or S.
+pub const SMALL_SPAN: u32 = 2;
+
+/// Axis-aligned piece of a route from its first point to its second, inclusive at both ends.
+pub type Segment = (Point, Point);
+
+/// Orthogonal route between two anchors, chosen from the sides they sit on:
+/// top/bottom to top/bottom is a Z-route (vertical, horizontal, vertical), left/right to
+/// left/right an S-route (horizontal, vertical, horizontal), and mixed sides a single-bend L.
+/// `bend` places the middle leg of a Z or S. When the ends are within `SMALL_SPAN` cells of
+/// each other both ways, a Z or S would only be a staircase of corners, so it collapses to an
+/// L that still enters the target the way the full route would. Drawing and hit-testing both
+/// go through here so they never disagree.
+///
+/// ```
+/// use dxgmr::model::{route_segments, Bend};
+///
+/// // Diagonally close ends turn once instead of zigzagging
+/// for (dx, dy) in [(1, 1), (1, 2), (2, 2), (-1, 2), (2, -1)] {
+///     let z = route_segments((5, 5), (5 + dx, 5 + dy), true, true, Bend::Middle);
+///     assert_eq!(z, vec![((5, 5), (5 + dx, 5)), ((5 + dx, 5), (5 + dx, 5 + dy))]);
+///     let s = route_segments((5, 5), (5 + dx, 5 + dy), false, false, Bend::Middle);
+///     assert_eq!(s, vec![((5, 5), (5, 5 + dy)), ((5, 5 + dy), (5 + dx, 5 + dy))]);
+/// }
+/// // Further apart there is room for the middle leg, wherever the bend puts it
+/// assert_eq!(route_segments((5, 5), (7, 9), true, true, Bend::Source).len(), 3);
+/// assert_eq!(route_segments((5, 5), (8, 6), false, false, Bend::Middle).len(), 3);
+/// ```
+///
+/// Hit-testing follows the L, so a click on its corner picks the connection:
+///
+/// ```
+/// use dxgmr::builder::DiagramBuilder;
+/// use dxgmr::model::AppState;
+///
+/// for dx in [-2, -1, 1, 2] {
+///     let diagram = DiagramBuilder::new("Close")
+///         .box_at(0, 0, "A")
+///         .box_at(dx, 5, "B")
+///         .connect(1, 2).arrow()
+///         .build();
+///     let state = AppState::from_diagram(diagram);
+///     let conn = &state.connections[0];
+///     let route = conn.route(&state.nodes).unwrap();
+///     assert_eq!(route.len(), 2);
+///     let (corner, end) = route[1];
+///     assert_eq!(corner.0, end.0); // The last leg drops straight onto the target
+///     assert!(conn.contains(corner.0, corner.1, &state.nodes));
+/// }
+/// ```
+pub fn route_segments(start: Point, end: Point, from_vertical: bool, to_vertical: bool, bend: Bend) -> Vec<Segment> {
+    let (x1, y1) = start;
+    let (x2, y2) = end;
+    let small = x1.abs_diff(x2) <= SMALL_SPAN && y1.abs_diff(y2) <= SMALL_SPAN;
+    match (from_vertical, to_vertical) {
+        (true, true) if small => vec![((x1, y1), (x2, y1)), ((x2, y1), (x2, y2))],
+        (false, false) if small => vec![((x1, y1), (x1, y2)), ((x1, y2), (x2, y2))],
+        (true, true) => {
+            let mid_y = bend.leg(y1, y2);
+            vec![((x1, y1), (x1, mid_y)), ((x1, mid_y), (x2, mid_y)), ((x2, mid_y), (x2, y2))]
+        }
+        (false, false) => {
+            let mid_x = bend.leg(x1, x2);
+            vec![((x1, y1), (mid_x, y1)), ((mid_x, y1), (mid_x, y2)), ((mid_x, y2), (x2, y2))]
+        }
+        (true, false) => vec![((x1, y1), (x1, y2)), ((x1, y2), (x2, y2))],
+        (false, true) => vec![((x1, y1), (x2, y1)), ((x2, y1), (x2, y2))],
+    }
+}
+
+/// Leftmost cell of a label `width` columns wide, plus the blank kept either side of it:
+/// centered on the longest horizontal run of the route, or on the longest vertical one
+/// (written across it) when the route never runs sideways.
+pub fn label_spot(segments: &[Segment], width: u16) -> Option<Point> {
+    // A straightened Z or S is still split at its zero-length middle leg; join the pieces
+    let mut runs: Vec<Segment> = Vec::new();
+    for &(a, b) in segments.iter().filter(|(a, b)| a != b) {
+        match runs.last_mut() {
+            Some((start, end)) if *end == a && (start.0 == b.0 || start.1 == b.1) => *end = b,
+            _ => runs.push((a, b)),
+        }
+    }
+    let longest = runs.into_iter()
+        .max_by_key(|&((x1, y1), (x2, y2))| (y1 == y2, x1.abs_diff(x2) + y1.abs_diff(y2)))
+        .or(segments.first().copied())?;
+    let ((x1, y1), (x2, y2)) = longest;
+    let (mid_x, mid_y) = ((x1 + x2).div_euclid(2), (y1 + y2).div_euclid(2));
+    Some((mid_x - ((width + 2) / 2) as i32, mid_y))
+}
+
+/// Which sides of a node follow the mouse during a resize drag.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResizeHandle {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl ResizeHandle {
+    /// The handle under `(x, y)` on the node's outline; interior points grab nothing.
+    pub fn at(node: &Node, x: i32, y: i32) -> Self {
+        let right = x == node.x + node.width as i32 - 1;
+        let bottom = y == node.y + node.height as i32 - 1;
+        Self { left: x == node.x && !right, right, top: y == node.y && !bottom, bottom }
+    }
+
+    pub fn is_corner(&self) -> bool {
+        (self.left || self.right) && (self.top || self.bottom)
+    }
 
-        if let (Some(f), Some(t)) = (from, to) {
-            let x1 = f.x + self.from_offset.0;
-            let y1 = f.y + self.from_offset.1;
-            let x2 = t.x + self.to_offset.0;
-            let y2 = t.y + self.to_offset.1;
+    pub fn is_edge(&self) -> bool {
+        !self.is_corner() && (self.left || self.right || self.top || self.bottom)
+    }
 
-            let vertical_first = self.from_offset.1 == 0 || self.from_offset.1 == f.height - 1;
+    /// Moves the grabbed sides to `(mx, my)`, keeping the opposite sides fixed and the node at
+    /// least its shape's `min_size` in each direction.
+    pub fn apply(&self, node: &mut Node, mx: i32, my: i32) {
+        let right = node.x + node.width as i32 - 1;
+        let bottom = node.y + node.height as i32 - 1;
+        let (min_w, min_h) = node.shape.min_size();
+        let span = |from: i32, to: i32, min: u16| (to - from + 1).clamp(min as i32, u16::MAX as i32) as u16;
+        if self.right {
+            node.width = span(node.x, mx, min_w);
+        } else if self.left {
+            node.x = mx.min(right + 1 - min_w as i32);
+            node.width = span(node.x, right, min_w);
+        }
+        if self.bottom {
+            node.height = span(node.y, my, min_h);
+        } else if self.top {
+            node.y = my.min(bottom + 1 - min_h as i32);
+            node.height = span(node.y, bottom, min_h);
+        }
+    }
+}
+
+/// Smallest width/height a box or frame can be resized to.
+pub const MIN_RESIZE: u16 = 3;
+
+/// Position and size typed at the geometry prompt (`=`). Fields left out keep their value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Geometry {
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+}
+
+impl Geometry {
+    /// Every field of a node, as the prompt starts out.
+    pub fn of(node: &Node) -> Self {
+        Self { x: Some(node.x), y: Some(node.y), width: Some(node.width), height: Some(node.height) }
+    }
 
-            if vertical_first {
-                let mid_y = (y1 + y2) / 2;
-                // V1
-                if mx == x1 && my >= y1.min(mid_y) && my <= y1.max(mid_y) { return true; }
-                // H
-                if my == mid_y && mx >= x1.min(x2) && mx <= x1.max(x2) { return true; }
-                // V2
-                if mx == x2 && my >= mid_y.min(y2) && my <= mid_y.max(y2) { return true; }
-            } else {
-                let mid_x = (x1 + x2) / 2;
-                // H1
-                if my == y1 && mx >= x1.min(mid_x) && mx <= x1.max(mid_x) { return true; }
-                // V
-                if mx == mid_x && my >= y1.min(y2) && my <= y1.max(y2) { return true; }
-                // H2
-                if my == y2 && mx >= mid_x.min(x2) && mx <= mid_x.max(x2) { return true; }
+    /// Reads `key=value` pairs split by spaces or commas: `x` and `y` take any whole number,
+    /// `w` / `width` and `h` / `height` a positive one. The error names the first bad pair.
+    ///
+    /// ```
+    /// use dxgmr::model::Geometry;
+    ///
+    /// let g = Geometry::parse("x=-3, y=4 w=20").unwrap();
+    /// assert_eq!((g.x, g.y, g.width, g.height), (Some(-3), Some(4), Some(20), None));
+    /// assert_eq!(Geometry::parse("w=0").unwrap_err(), "w needs a positive number, not \"0\"");
+    /// assert_eq!(Geometry::parse("z=1").unwrap_err(), "Unknown field \"z\" (use x, y, w, h)");
+    /// assert_eq!(Geometry::parse("x 4").unwrap_err(), "Expected key=value, got \"x\"");
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut geometry = Self::default();
+        for pair in input.split([' ', ',']).filter(|p| !p.is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("Expected key=value, got \"{}\"", pair));
+            };
+            let coordinate = || value.parse::<i32>().map_err(|_| format!("{} needs a whole number, not \"{}\"", key, value));
+            let size = || value.parse::<u16>().ok().filter(|&v| v > 0)
+                .ok_or_else(|| format!("{} needs a positive number, not \"{}\"", key, value));
+            match key.to_ascii_lowercase().as_str() {
+                "x" => geometry.x = Some(coordinate()?),
+                "y" => geometry.y = Some(coordinate()?),
+                "w" | "width" => geometry.width = Some(size()?),
+                "h" | "height" => geometry.height = Some(size()?),
+                _ => return Err(format!("Unknown field \"{}\" (use x, y, w, h)", key)),
             }
         }
-        false
+        if geometry == Self::default() {
+            return Err(String::from("Nothing to apply: type e.g. x=10 y=4 w=20 h=5"));
+        }
+        Ok(geometry)
+    }
+}
+
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = [("x", self.x), ("y", self.y), ("w", self.width.map(i32::from)), ("h", self.height.map(i32::from))];
+        let pairs: Vec<String> = fields.iter().filter_map(|(k, v)| v.map(|v| format!("{}={}", k, v))).collect();
+        write!(f, "{}", pairs.join(" "))
     }
 }
 
@@ -74,7 +712,7 @@ pub enum PartialConnection {
     Starting {
         from_id: usize,
         from_offset: (u16, u16),
-        current_pos: (u16, u16),
+        current_pos: Point,
     },
 }
 
@@ -85,85 +723,1475 @@ pub enum AppMode {
     Leader,        // Spacebar hit, waiting for command
     Resize(usize), // Node ID being resized
     Help,          // Showing command help
-    ContextMenu { x: u16, y: u16, selected_index: usize },
+    Stats,         // Showing diagram statistics
+    Log(usize),    // Showing the message log, scrolled this many entries back
+    QuickConnect { from: Option<usize>, typed: Option<usize> }, // Linking by badge number: chosen source, digits so far
+    ContextMenu { x: u16, y: u16, selected_index: usize, target: MenuTarget },
+    Label(usize),  // Connection index whose label is being typed
+    Geometry(usize), // Node ID whose position / size is being typed (`AppState::geometry_input`)
+    ConfirmQuit,   // Quitting; asking whether to save this diagram's changes first
+    ConfirmReload, // The .json changed on disk; asking whether to drop unsaved changes for it
+}
+
+/// What the right-click landed on; decides which entries the context menu offers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuTarget {
+    Canvas,
+    Node(usize),       // Node ID
+    Connection(usize), // Index into connections
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuAction {
+    NewShape(ShapeType),
+    EditText,
+    Resize,
+    Duplicate,
+    ToggleLock,
+    Connect { arrow: bool },
+    ToggleArrow,
+    ToggleWeight,
+    Reverse,
+    EditLabel,
+    Delete,
+    Cancel,
+}
+
+impl MenuAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MenuAction::NewShape(ShapeType::Box) => "New Box",
+            MenuAction::NewShape(ShapeType::Diamond) => "New Diamond",
+            MenuAction::NewShape(ShapeType::Text) => "New Text",
+            MenuAction::NewShape(ShapeType::Frame) => "New Frame",
+            MenuAction::EditText => "Edit Text",
+            MenuAction::Resize => "Resize",
+            MenuAction::Duplicate => "Duplicate",
+            MenuAction::ToggleLock => "Lock / Unlock",
+            MenuAction::Connect { arrow: false } => "Start Connector",
+            MenuAction::Connect { arrow: true } => "Start Arrow",
+            MenuAction::ToggleArrow => "Cycle Arrows",
+            MenuAction::ToggleWeight => "Thin / Thick",
+            MenuAction::Reverse => "Reverse",
+            MenuAction::EditLabel => "Edit label",
+            MenuAction::Delete => "Delete",
+            MenuAction::Cancel => "Cancel",
+        }
+    }
+}
+
+/// A context menu row: something to run, or a divider that navigation skips over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuItem {
+    Action(MenuAction),
+    Separator,
+}
+
+impl MenuItem {
+    pub fn action(&self) -> Option<MenuAction> {
+        match self {
+            MenuItem::Action(action) => Some(*action),
+            MenuItem::Separator => None,
+        }
+    }
+}
+
+impl MenuTarget {
+    pub fn items(&self) -> Vec<MenuItem> {
+        use MenuItem::{Action, Separator};
+        match self {
+            MenuTarget::Canvas => vec![
+                Action(MenuAction::NewShape(ShapeType::Box)),
+                Action(MenuAction::NewShape(ShapeType::Diamond)),
+                Action(MenuAction::NewShape(ShapeType::Text)),
+                Action(MenuAction::NewShape(ShapeType::Frame)),
+                Separator,
+                Action(MenuAction::Cancel),
+            ],
+            MenuTarget::Node(_) => vec![
+                Action(MenuAction::EditText),
+                Action(MenuAction::Resize),
+                Action(MenuAction::Duplicate),
+                Action(MenuAction::ToggleLock),
+                Separator,
+                Action(MenuAction::Connect { arrow: false }),
+                Action(MenuAction::Connect { arrow: true }),
+                Action(MenuAction::Delete),
+                Separator,
+                Action(MenuAction::Cancel),
+            ],
+            MenuTarget::Connection(_) => vec![
+                Action(MenuAction::ToggleArrow),
+                Action(MenuAction::ToggleWeight),
+                Action(MenuAction::Reverse),
+                Action(MenuAction::EditLabel),
+                Action(MenuAction::Delete),
+                Separator,
+                Action(MenuAction::Cancel),
+            ],
+        }
+    }
+}
+
+/// The nearest selectable row after (or before) `from`, skipping separators.
+/// Stays on `from` when there is nothing further that way.
+pub fn step_menu(items: &[MenuItem], from: usize, forward: bool) -> usize {
+    let found = if forward {
+        items.iter().enumerate().skip(from + 1).find(|(_, item)| item.action().is_some())
+    } else {
+        items.iter().enumerate().take(from).rev().find(|(_, item)| item.action().is_some())
+    };
+    found.map_or(from, |(i, _)| i)
+}
+
+/// Characters for one kind of connection line.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct LineGlyphs {
+    pub horizontal: char,
+    pub vertical: char,
+    pub join: char, // Bends and crossings
 }
 
+/// Every character a connection is drawn with. Saved with the diagram, so the look can be tuned
+/// by editing the `.json`; fields left out keep their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GlyphSet {
+    pub thin: LineGlyphs,
+    pub thin_highlighted: LineGlyphs,
+    pub thick: LineGlyphs,
+    pub thick_highlighted: LineGlyphs,
+    pub start: char, // Where a link leaves its source
+    pub start_highlighted: char,
+    pub arrow_up: char,
+    pub arrow_down: char,
+    pub arrow_left: char,
+    pub arrow_right: char,
+}
+
+impl Default for GlyphSet {
+    fn default() -> Self {
+        let line = |horizontal, vertical, join| LineGlyphs { horizontal, vertical, join };
+        Self {
+            thin: line('-', '|', '+'),
+            thin_highlighted: line('=', '#', '#'),
+            thick: line('=', 'H', '#'),
+            thick_highlighted: line('#', '#', '#'),
+            start: 'o',
+            start_highlighted: '@',
+            arrow_up: '^',
+            arrow_down: 'v',
+            arrow_left: '<',
+            arrow_right: '>',
+        }
+    }
+}
+
+impl GlyphSet {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn line(&self, weight: Weight, highlighted: bool) -> LineGlyphs {
+        match (weight, highlighted) {
+            (Weight::Thin, false) => self.thin,
+            (Weight::Thin, true) => self.thin_highlighted,
+            (Weight::Thick, false) => self.thick,
+            (Weight::Thick, true) => self.thick_highlighted,
+        }
+    }
+}
+
+/// Corner of the view (or of an export) the legend sits in.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum Corner {
+    #[default]
+    TopRight,
+    BottomRight,
+    BottomLeft,
+    TopLeft,
+}
+
+impl Corner {
+    pub fn next(self) -> Self {
+        match self {
+            Corner::TopRight => Corner::BottomRight,
+            Corner::BottomRight => Corner::BottomLeft,
+            Corner::BottomLeft => Corner::TopLeft,
+            Corner::TopLeft => Corner::TopRight,
+        }
+    }
+
+    /// The bottom corner on the same side.
+    pub fn lowered(self) -> Self {
+        match self {
+            Corner::TopRight | Corner::BottomRight => Corner::BottomRight,
+            Corner::TopLeft | Corner::BottomLeft => Corner::BottomLeft,
+        }
+    }
+}
+
+/// A boxed key, one entry per line, drawn over a corner of the view. Exports put it below the
+/// drawing on the same side instead, so it never hides a shape. It isn't a node, so clicks go
+/// straight through it. Entries are edited in the `.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Legend {
+    pub entries: Vec<String>,
+    pub corner: Corner,
+    pub visible: bool,
+}
+
+impl Legend {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Width and height of the box, border included. `None` when hidden or empty.
+    pub fn size(&self) -> Option<(u16, u16)> {
+        if !self.visible || self.entries.is_empty() { return None; }
+        let widest = self.entries.iter().map(|e| display_width(e)).max().unwrap_or(0);
+        // A space either side of the text, and room for the " Legend " heading
+        Some(((widest + 4).max(LEGEND_HEADING.len() as u16 + 4), self.entries.len() as u16 + 2))
+    }
+}
+
+/// Heading written into the legend's top border.
+pub const LEGEND_HEADING: &str = " Legend ";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Diagram {
     pub title: String,
     pub nodes: Vec<Node>,
     pub connections: Vec<Connection>,
+    #[serde(default)]
+    pub camera_offset: (i32, i32), // Viewport restored on reopen
+    #[serde(default)]
+    pub selected_connection: Option<usize>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<ShapeType, String>, // Starting text for new shapes, per type
+    #[serde(default, skip_serializing_if = "GlyphSet::is_default")]
+    pub glyphs: GlyphSet,
+    #[serde(default)]
+    pub edges_behind: bool, // Draw connections first so shapes cover them
+    #[serde(default = "default_tab_width")]
+    pub tab_width: u16, // Columns between tab stops when tabs in text are expanded
+    #[serde(default, skip_serializing_if = "Legend::is_default")]
+    pub legend: Legend,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>, // RFC 3339, UTC: when the diagram was first saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>, // RFC 3339, UTC: the latest save
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+pub const DEFAULT_NUDGE_STEP: u16 = 5;
+
+/// Cells the camera pans per mouse wheel notch.
+pub const SCROLL_STEP: u16 = 3;
+
+/// Empty cells kept between a fitted Frame's border and its contents.
+pub const FRAME_MARGIN: u16 = 1;
+
+/// Upper bound on `AppState::tidy`'s separation passes, in case pushes keep creating overlaps.
+pub const TIDY_MAX_PASSES: usize = 100;
+
+/// The traditional fixed export width, available via `--classic`.
+pub const CLASSIC_EXPORT_WIDTH: u16 = 79;
+
+/// How many cells away from the requested spot placement looks for free space.
+pub const MAX_PLACEMENT_SEARCH: u16 = 60;
+
+/// How many cells off a connection a click may land and still pick it.
+pub const PICK_TOLERANCE: u16 = 1;
+
+/// Longest gap between two clicks on a node that still counts as a double click.
+pub const DOUBLE_CLICK_MS: u128 = 400;
+
+/// Status messages kept in the message log before the oldest are dropped.
+pub const MESSAGE_LOG_LEN: usize = 200;
+
+/// Side of the square cells the spatial index buckets the canvas into.
+pub const INDEX_CELL: i32 = 16;
+
+/// Which nodes and connections have a bounding box touching each `INDEX_CELL` square, so hit
+/// tests only look at a handful of candidates. Stored indices point into `AppState::nodes` /
+/// `connections`; `AppState::refresh_index` rebuilds it after an `edit`.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    built_at: Option<u64>, // `AppState::edits` when the buckets were built
+    counts: (usize, usize), // Node and connection counts at that point
+    nodes: HashMap<Point, Vec<usize>>,
+    connections: HashMap<Point, Vec<usize>>,
+}
+
+impl SpatialIndex {
+    fn insert(buckets: &mut HashMap<Point, Vec<usize>>, (x1, y1): Point, (x2, y2): Point, idx: usize) {
+        for by in y1.div_euclid(INDEX_CELL)..=y2.div_euclid(INDEX_CELL) {
+            for bx in x1.div_euclid(INDEX_CELL)..=x2.div_euclid(INDEX_CELL) {
+                buckets.entry((bx, by)).or_default().push(idx);
+            }
+        }
+    }
+
+    fn candidates(buckets: &HashMap<Point, Vec<usize>>, x: i32, y: i32) -> &[usize] {
+        buckets.get(&(x.div_euclid(INDEX_CELL), y.div_euclid(INDEX_CELL))).map_or(&[], |v| v.as_slice())
+    }
 }
 
 pub struct AppState {
     pub title: String,
+    pub file_stem: String, // `<file_stem>.json` etc. on disk; from the title unless opened under another name
     pub nodes: Vec<Node>,
     pub connections: Vec<Connection>,
     pub dragging_node_id: Option<usize>,
     pub drag_offset: (u16, u16),
+    pub drag_start: Option<Point>, // Where the mouse went down on the dragged node, to tell clicks from drags
+    pub last_click: Option<(Instant, usize)>, // When and on which node the last plain click landed
+    pub drag_group: Vec<usize>, // Nodes carried along with a dragged Frame
+    pub snap_guides: (Option<i32>, Option<i32>), // Alignment guide column/row shown while dragging
     pub camera_offset: (i32, i32),
+    pub pan_start: Option<(u16, u16)>, // Screen position where a mouse pan began
+    pub pan_last: (u16, u16),          // Screen position seen on the previous pan event
+    pub rubber_band: Option<(Point, Point)>, // Selection rectangle start/current (world coords)
+    pub hover_pos: Option<Point>, // Last mouse position over the canvas (world coords)
     pub partial_connection: Option<PartialConnection>,
     pub selected_connection_index: Option<usize>,
     pub resizing_node_id: Option<usize>,
+    pub resize_handle: ResizeHandle,
     pub connection_source_id: Option<usize>,
+    pub connection_source_offset: Option<(u16, u16)>, // Anchor clicked on the source in two-click mode
+    pub click_connect: bool, // Two-click connect armed: next clicks pick source, then target
     pub connection_has_arrow: bool,
     pub mode: AppMode,
+    pub nudge_step: u16, // Cells moved/panned per Shift+Arrow
+    pub message_log: VecDeque<String>, // Past status messages, oldest first, stamped with session time
+    pub started_at: Instant,
+    pub last_logged: Option<String>, // Unstamped text of the newest log entry
+    pub export_width: Option<u16>, // Columns for .txt/clipboard output; None fits the diagram
+    pub export_selection: bool, // Export only the selected nodes and the links among them
+    pub show_ids: bool, // Draw each node's id as a badge (exports include them too)
+    pub show_minimap: bool, // Overview of the whole diagram in the corner of the screen (never exported)
+    pub templates: HashMap<ShapeType, String>,
+    pub template_pending: Option<usize>, // New node still showing its template; its first keystroke replaces it
+    pub style_buffer: Option<StyleSnapshot>, // Look yanked by `copy_style`; not saved
+    pub geometry_input: String, // Text typed so far at the geometry prompt
+    pub glyphs: GlyphSet, // Characters connections are drawn with
+    pub edges_behind: bool, // Shapes drawn over connections instead of under them
+    pub tab_width: u16, // Tab stop spacing used when text is typed, pasted or loaded
+    pub legend: Legend,
+    pub created: Option<String>, // Provenance kept from the file; see `Diagram`
+    pub modified: Option<String>,
+    pub author: Option<String>,
+    pub version: u64, // Bumped by `touch` / `edit` when something drawn changes; the canvas is reused until then
+    pub spatial: SpatialIndex,
+    pub edits: u64, // Bumped by `edit` for every change to the diagram itself
+    saved_edits: u64, // `edits` when the diagram was last loaded or saved
+    pub watch_file: bool, // Reload when `<title>.json` changes on disk
+    pub disk_mtime: Option<SystemTime>, // Modification time of `<title>.json` when last loaded or saved
+    pub snap_links: bool, // New links between nearly aligned shapes are straightened (`align_anchors`)
+    pub leader_timeout_ms: u64, // Leader falls back to Normal after this long without a key; 0 waits forever
+    pub leader_since: Option<Instant>, // When Leader was entered (or last saw a key), while it's armed
+    pub last_selected_id: Option<usize>, // Shape selected before the current one, for Ctrl+O
+    current_selected_id: Option<usize>, // Latest shape selected on its own, kept while nothing is
 }
 
 impl AppState {
     pub fn new(title: String) -> Self {
-        Self {
+        let mut state = Self {
+            file_stem: sanitize_filename(&title),
             title,
             nodes: Vec::new(),
             connections: Vec::new(),
             dragging_node_id: None,
             drag_offset: (0, 0),
+            drag_start: None,
+            last_click: None,
+            drag_group: Vec::new(),
+            snap_guides: (None, None),
             camera_offset: (0, 0),
+            pan_start: None,
+            pan_last: (0, 0),
+            rubber_band: None,
+            hover_pos: None,
             partial_connection: None,
             selected_connection_index: None,
             resizing_node_id: None,
+            resize_handle: ResizeHandle::default(),
             connection_source_id: None,
+            connection_source_offset: None,
+            click_connect: false,
             connection_has_arrow: false,
             mode: AppMode::Normal,
-        }
+            nudge_step: DEFAULT_NUDGE_STEP,
+            message_log: VecDeque::new(),
+            started_at: Instant::now(),
+            last_logged: None,
+            export_width: None,
+            export_selection: false,
+            show_ids: false,
+            show_minimap: false,
+            templates: HashMap::new(),
+            glyphs: GlyphSet::default(),
+            edges_behind: false,
+            tab_width: TAB_WIDTH,
+            legend: Legend::default(),
+            created: None,
+            modified: None,
+            author: None,
+            version: 0,
+            spatial: SpatialIndex::default(),
+            edits: 0,
+            saved_edits: 0,
+            watch_file: false,
+            disk_mtime: None,
+            snap_links: true,
+            leader_timeout_ms: 0,
+            leader_since: None,
+            last_selected_id: None,
+            current_selected_id: None,
+            template_pending: None,
+            style_buffer: None,
+            geometry_input: String::new(),
+        };
+        state.mark_saved();
+        state
     }
 
     pub fn from_diagram(diagram: Diagram) -> Self {
-        let mut state = Self::new(diagram.title);
-        state.nodes = diagram.nodes;
-        state.connections = diagram.connections;
+        let mut state = Self::new(diagram.title.clone());
+        state.camera_offset = diagram.camera_offset;
+        state.replace_diagram(diagram);
         state
     }
 
+    /// The title, or the file name stem for a diagram left untitled.
+    pub fn display_title(&self) -> &str {
+        if self.title.is_empty() { &self.file_stem } else { &self.title }
+    }
+
+    /// The diagram's file with the given extension, e.g. `file_name("json")`.
+    pub fn file_name(&self, extension: &str) -> String {
+        format!("{}.{}", self.file_stem, extension)
+    }
+
+    /// Swaps in a diagram's content, as saved, keeping the title, view and session settings.
+    /// Drags, pending links and edits in progress are dropped, as their ids may be gone.
+    pub fn replace_diagram(&mut self, diagram: Diagram) {
+        self.nodes = diagram.nodes;
+        self.connections = diagram.connections;
+        self.tab_width = diagram.tab_width;
+        self.legend = diagram.legend;
+        self.created = diagram.created;
+        self.modified = diagram.modified;
+        self.author = diagram.author;
+        // Text written by hand or by another program may hold raw tabs; lay them out once here
+        // so sizes and drawing only ever see spaces
+        for n in &mut self.nodes {
+            if n.text.contains(|c: char| c.is_control() && c != '\n') {
+                n.text = clean_label(&n.text, self.tab_width);
+                n.fit_to_text();
+            }
+        }
+        for c in &mut self.connections {
+            c.label = clean_label(&c.label, self.tab_width).replace('\n', " ");
+        }
+        for entry in &mut self.legend.entries {
+            *entry = clean_label(entry, self.tab_width).replace('\n', " ");
+        }
+        self.selected_connection_index = diagram.selected_connection;
+        self.templates = diagram.templates;
+        self.glyphs = diagram.glyphs;
+        self.edges_behind = diagram.edges_behind;
+        self.dragging_node_id = None;
+        self.resizing_node_id = None;
+        self.drag_group.clear();
+        self.partial_connection = None;
+        self.connection_source_id = None;
+        self.connection_source_offset = None;
+        self.click_connect = false;
+        self.rubber_band = None;
+        self.template_pending = None;
+        self.last_selected_id = None;
+        self.current_selected_id = None;
+        self.mode = AppMode::Normal;
+        self.normalize_selection();
+        self.spatial = SpatialIndex::default();
+        self.mark_saved();
+    }
+
     pub fn to_diagram(&self) -> Diagram {
         Diagram {
             title: self.title.clone(),
             nodes: self.nodes.clone(),
             connections: self.connections.clone(),
+            camera_offset: self.camera_offset,
+            selected_connection: self.selected_connection_index,
+            templates: self.templates.clone(),
+            glyphs: self.glyphs.clone(),
+            edges_behind: self.edges_behind,
+            tab_width: self.tab_width,
+            legend: self.legend.clone(),
+            created: self.created.clone(),
+            modified: self.modified.clone(),
+            author: self.author.clone(),
+        }
+    }
+
+    /// Stamps a save at `now`: the modified time, and the created time on a first save.
+    pub fn stamp_save(&mut self, now: SystemTime) {
+        let stamp = rfc3339_utc(now);
+        self.created.get_or_insert_with(|| stamp.clone());
+        self.modified = Some(stamp);
+    }
+
+    /// Records the current diagram as saved.
+    pub fn mark_saved(&mut self) {
+        self.saved_edits = self.edits;
+    }
+
+    /// Whether the diagram was edited since it was loaded or last saved. Only `edit` counts, so
+    /// clicking around, panning or an `--author` default alone doesn't nag on quit.
+    pub fn is_dirty(&self) -> bool {
+        self.edits != self.saved_edits
+    }
+
+    /// Notes a change of the single selected shape, so `last_selected_id` holds the one before.
+    /// Run after every event; a stale id (its shape deleted) is forgotten.
+    pub fn track_selection(&mut self) {
+        let mut selected = self.nodes.iter().filter(|n| n.selected);
+        if let (Some(node), None) = (selected.next(), selected.next())
+            && self.current_selected_id != Some(node.id) {
+            self.last_selected_id = self.current_selected_id.replace(node.id);
+        }
+        if self.last_selected_id.is_some_and(|id| !self.nodes.iter().any(|n| n.id == id)) {
+            self.last_selected_id = None;
+        }
+    }
+
+    /// Marks a change to what is drawn but not to the diagram itself (selection, mode, camera),
+    /// so the next frame renders afresh.
+    pub fn touch(&mut self) {
+        self.version = self.version.wrapping_add(1);
+    }
+
+    /// Marks a change to the diagram itself: shapes, links, text or anything else that's saved.
+    /// It then has unsaved changes until `mark_saved`.
+    pub fn edit(&mut self) {
+        self.edits = self.edits.wrapping_add(1);
+        self.touch();
+    }
+
+    /// Id for a new node: one past the highest live id, so it can never clash with one.
+    pub fn next_id(&self) -> usize {
+        self.nodes.iter().map(|n| n.id).max().unwrap_or(0) + 1
+    }
+
+    /// Renumbers nodes 1..=N, keeping their relative order, and rewrites every connection and
+    /// in-flight reference to match. Returns how many nodes changed id.
+    ///
+    /// Ids must already be unique (`repair` makes them so): two nodes sharing an id would both
+    /// take one new id, and their connections could no longer be told apart.
+    pub fn compact_ids(&mut self) -> usize {
+        let mut old_ids: Vec<usize> = self.nodes.iter().map(|n| n.id).collect();
+        old_ids.sort_unstable();
+        debug_assert!(old_ids.windows(2).all(|w| w[0] != w[1]), "compact_ids needs unique node ids");
+        let remap: HashMap<usize, usize> = old_ids.iter().enumerate().map(|(i, &id)| (id, i + 1)).collect();
+        let new_id = |id: usize| remap.get(&id).copied().unwrap_or(id);
+
+        let mut changed = 0;
+        for n in &mut self.nodes {
+            if new_id(n.id) != n.id { changed += 1; }
+            n.id = new_id(n.id);
+        }
+        for c in &mut self.connections {
+            c.from_id = new_id(c.from_id);
+            if let Endpoint::NodeAnchor { to_id, .. } = &mut c.to {
+                *to_id = new_id(*to_id);
+            }
+        }
+        for id in [
+            &mut self.dragging_node_id,
+            &mut self.resizing_node_id,
+            &mut self.connection_source_id,
+            &mut self.template_pending,
+            &mut self.last_selected_id,
+            &mut self.current_selected_id,
+        ].into_iter().flatten() {
+            *id = new_id(*id);
+        }
+        for id in &mut self.drag_group { *id = new_id(*id); }
+        if let Some(PartialConnection::Starting { from_id, .. }) = &mut self.partial_connection {
+            *from_id = new_id(*from_id);
+        }
+        changed
+    }
+
+    /// Repairs a loaded diagram in place: gives zero-size nodes a minimal size, reassigns
+    /// duplicate node IDs, drops connections whose endpoints no longer exist and clamps anchor
+    /// offsets into their node. Returns a description of every fix applied.
+    pub fn repair(&mut self) -> Vec<String> {
+        let mut fixes = Vec::new();
+
+        for n in &mut self.nodes {
+            if n.width == 0 || n.height == 0 {
+                fixes.push(format!("node {} had zero size", n.id));
+                n.width = n.width.max(1);
+                n.height = n.height.max(1);
+            }
+        }
+
+        // Later duplicates get fresh IDs; existing references keep pointing at the first one
+        let mut seen = Vec::new();
+        let mut next_id = self.next_id();
+        for n in &mut self.nodes {
+            if seen.contains(&n.id) {
+                fixes.push(format!("duplicate node id {} renumbered to {}", n.id, next_id));
+                n.id = next_id;
+                next_id += 1;
+            }
+            seen.push(n.id);
+        }
+
+        let nodes = &self.nodes;
+        let before = self.connections.len();
+        self.connections.retain(|c| {
+            nodes.iter().any(|n| n.id == c.from_id)
+                && c.to.node_id().is_none_or(|id| nodes.iter().any(|n| n.id == id))
+        });
+        for _ in self.connections.len()..before {
+            fixes.push(String::from("dangling connection removed"));
+        }
+
+        let clamp = |offset: &mut (u16, u16), node: &Node| -> bool {
+            let clamped = node.clamp_offset(*offset);
+            let changed = clamped != *offset;
+            *offset = clamped;
+            changed
+        };
+        for c in &mut self.connections {
+            if let Some(f) = nodes.iter().find(|n| n.id == c.from_id)
+                && clamp(&mut c.from_offset, f) {
+                fixes.push(format!("connection offset clamped on node {}", f.id));
+            }
+            if let Endpoint::NodeAnchor { to_id, to_offset } = &mut c.to
+                && let Some(t) = nodes.iter().find(|n| n.id == *to_id)
+                && clamp(to_offset, t) {
+                fixes.push(format!("connection offset clamped on node {}", t.id));
+            }
+        }
+
+        self.normalize_selection();
+        fixes
+    }
+
+    /// Keeps at most one selected node (the front-most) and drops a connection selection that is
+    /// out of bounds or competes with a node selection. Hand-edited or older files may carry both.
+    pub fn normalize_selection(&mut self) {
+        let keep = self.nodes.iter().rposition(|n| n.selected);
+        for (i, n) in self.nodes.iter_mut().enumerate() {
+            n.selected = Some(i) == keep;
+        }
+        if keep.is_some() || self.selected_connection_index.is_some_and(|i| i >= self.connections.len()) {
+            self.selected_connection_index = None;
+        }
+    }
+
+    /// Bounding box `(x, y, width, height)` of every node and free connection endpoint, in world
+    /// coordinates. `None` for an empty diagram.
+    pub fn bounds(&self) -> Option<(i32, i32, u16, u16)> {
+        let points = self.nodes.iter()
+            .flat_map(|n| [(n.x, n.y), n.point_at((n.width.saturating_sub(1), n.height.saturating_sub(1)))])
+            .chain(self.connections.iter().filter_map(|c| match c.to {
+                Endpoint::FreePoint { to_point } => Some(to_point),
+                Endpoint::NodeAnchor { .. } => None,
+            }));
+        let (min_x, min_y, max_x, max_y) = points.fold(None, |acc: Option<(i32, i32, i32, i32)>, (x, y)| match acc {
+            None => Some((x, y, x, y)),
+            Some((x1, y1, x2, y2)) => Some((x1.min(x), y1.min(y), x2.max(x), y2.max(y))),
+        })?;
+        let span = |lo: i32, hi: i32| (hi - lo + 1).min(u16::MAX as i32) as u16;
+        Some((min_x, min_y, span(min_x, max_x), span(min_y, max_y)))
+    }
+
+    /// The node after (or before, when `reverse`) `current` in reading order: top-to-bottom,
+    /// then left-to-right. Independent of the Vec order, which tracks z-order for rendering.
+    pub fn next_in_reading_order(&self, current: Option<usize>, reverse: bool) -> Option<usize> {
+        let mut order: Vec<&Node> = self.nodes.iter().collect();
+        order.sort_by_key(|n| (n.y, n.x, n.id));
+        let len = order.len();
+        if len == 0 { return None; }
+        let next_idx = match current.and_then(|id| order.iter().position(|n| n.id == id)) {
+            Some(idx) if reverse => (idx + len - 1) % len,
+            Some(idx) => (idx + 1) % len,
+            None if reverse => len - 1,
+            None => 0,
+        };
+        Some(order[next_idx].id)
+    }
+
+    /// The node closest to `from_id` among those whose center lies in the half-plane pointed at by
+    /// the unit direction `(dx, dy)`, e.g. `(1, 0)` for "to the right".
+    pub fn nearest_in_direction(&self, from_id: usize, dx: i32, dy: i32) -> Option<usize> {
+        let center = |n: &Node| n.center();
+        let from = self.nodes.iter().find(|n| n.id == from_id)?;
+        let (fx, fy) = center(from);
+        self.nodes.iter()
+            .filter(|n| n.id != from_id)
+            .filter_map(|n| {
+                let (cx, cy) = center(n);
+                let (rx, ry) = (cx - fx, cy - fy);
+                (rx * dx + ry * dy > 0).then_some((rx * rx + ry * ry, n.id))
+            })
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    /// The Frame a node belongs to: the smallest one whose interior fully holds it, so a shape
+    /// where two frames overlap goes to the tighter one. Ties go to the frame drawn on top.
+    pub fn parent_frame(&self, id: usize) -> Option<usize> {
+        let node = self.nodes.iter().find(|n| n.id == id)?;
+        self.nodes.iter().enumerate()
+            .filter(|(_, f)| f.shape == ShapeType::Frame && f.interior_holds(node))
+            .min_by_key(|&(i, f)| (f.width as u32 * f.height as u32, std::cmp::Reverse(i)))
+            .map(|(_, f)| f.id)
+    }
+
+    /// IDs of the nodes belonging to the given Frame (see `parent_frame`), including inner
+    /// frames and everything nested inside them.
+    pub fn nodes_in_frame(&self, frame_id: usize) -> Vec<usize> {
+        if !self.nodes.iter().any(|n| n.id == frame_id && n.shape == ShapeType::Frame) {
+            return Vec::new();
+        }
+        let parents: HashMap<usize, usize> = self.nodes.iter()
+            .filter_map(|n| Some((n.id, self.parent_frame(n.id)?)))
+            .collect();
+        // A parent is always strictly larger than its child, so these chains end
+        self.nodes.iter()
+            .map(|n| n.id)
+            .filter(|&id| {
+                let mut current = id;
+                while let Some(&parent) = parents.get(&current) {
+                    if parent == frame_id { return true; }
+                    current = parent;
+                }
+                false
+            })
+            .collect()
+    }
+
+    /// A Markdown table of the nodes (id, shape, text) followed by a list of the connections,
+    /// for pasting into design docs. Pipes and line breaks in labels are escaped so the table
+    /// stays intact.
+    pub fn to_markdown_summary(&self) -> String {
+        let cell = |text: &str| text.replace('|', "\\|").replace('\n', "<br>");
+        let name = |id: usize| match self.nodes.iter().find(|n| n.id == id) {
+            Some(n) if !n.text.trim().is_empty() => format!("{} ({})", id, n.text.split_whitespace().collect::<Vec<_>>().join(" ")),
+            _ => id.to_string(),
+        };
+
+        let mut nodes: Vec<&Node> = self.nodes.iter().collect();
+        nodes.sort_by_key(|n| n.id);
+        let mut out = format!("## {}\n\n| Id | Shape | Text |\n| --- | --- | --- |\n", self.title);
+        for n in nodes {
+            out.push_str(&format!("| {} | {:?} | {} |\n", n.id, n.shape, cell(&n.text)));
+        }
+
+        if !self.connections.is_empty() {
+            out.push_str("\n**Connections**\n\n");
+            for c in &self.connections {
+                let link = match (c.has_arrow, c.bidirectional) {
+                    (true, true) => "<->",
+                    (true, false) => "->",
+                    (false, _) => "--",
+                };
+                let target = match c.to {
+                    Endpoint::NodeAnchor { to_id, .. } => name(to_id),
+                    Endpoint::FreePoint { to_point } => format!("point ({}, {})", to_point.0, to_point.1),
+                };
+                let label = if c.label.is_empty() { String::new() } else { format!(": {}", c.label) };
+                out.push_str(&format!("- {} {} {}{}\n", name(c.from_id), link, target, label));
+            }
+        }
+        out
+    }
+
+    /// A copy holding only the selected nodes and the connections among them (plus free-point
+    /// arrows leaving them). None if nothing is selected.
+    pub fn selection_subset(&self) -> Option<AppState> {
+        let ids: Vec<usize> = self.nodes.iter().filter(|n| n.selected).map(|n| n.id).collect();
+        if ids.is_empty() { return None; }
+        let mut subset = AppState::new(self.title.clone());
+        subset.show_ids = self.show_ids;
+        subset.glyphs = self.glyphs.clone();
+        subset.edges_behind = self.edges_behind;
+        subset.nodes = self.nodes.iter().filter(|n| n.selected).cloned().collect();
+        for n in &mut subset.nodes { n.selected = false; }
+        subset.connections = self.connections.iter()
+            .filter(|c| ids.contains(&c.from_id) && c.to.node_id().is_none_or(|id| ids.contains(&id)))
+            .cloned()
+            .collect();
+        Some(subset)
+    }
+
+    /// World point exports start from: the top-left corner of `bounds`, wherever the camera is,
+    /// so nothing above or left of the view is cut off. The origin for an empty diagram.
+    pub fn export_origin(&self) -> Point {
+        self.bounds().map_or((0, 0), |(x, y, _, _)| (x, y))
+    }
+
+    /// Columns used when exporting: the configured width, or just enough to show everything
+    /// right of `export_origin` (plus one for arrowheads nudged past a border) and the legend.
+    pub fn export_columns(&self) -> u16 {
+        self.export_width.unwrap_or_else(|| {
+            let right = self.bounds().map_or(0, |(x, _, w, _)| x + w as i32 + 1);
+            let legend = self.legend.size().map_or(0, |(w, _)| w);
+            ((right - self.export_origin().0).clamp(1, u16::MAX as i32) as u16).max(legend)
+        })
+    }
+
+    /// Rows used when exporting: everything from `export_origin` down to the lowest shape (plus
+    /// one for arrowheads nudged past a border), then the legend under that. Like
+    /// `export_columns` it ignores the terminal and the camera, so neither a small window nor a
+    /// scrolled view clips the saved diagram.
+    ///
+    /// ```
+    /// use dxgmr::model::{AppState, ShapeType};
+    /// use dxgmr::renderer::write_ascii;
+    ///
+    /// let mut state = AppState::new(String::from("Tall"));
+    /// state.add_node(ShapeType::Box, 0, 0, "Top");
+    /// state.add_node(ShapeType::Box, 0, 200, "Bottom");
+    /// assert_eq!(state.export_rows(), 206);
+    ///
+    /// let mut out = Vec::new();
+    /// write_ascii(&state, state.export_columns(), state.export_rows(), &mut out).unwrap();
+    /// let text = String::from_utf8(out).unwrap();
+    /// assert!(text.lines().nth(202).unwrap().contains("Bottom"));
+    /// assert_eq!(text.lines().count(), 205);
+    /// ```
+    pub fn export_rows(&self) -> u16 {
+        let bottom = self.bounds().map_or(0, |(_, y, _, h)| y + h as i32 + 1);
+        let legend = self.legend.size().map_or(0, |(_, h)| h);
+        ((bottom - self.export_origin().1).clamp(1, u16::MAX as i32) as u16).saturating_add(legend)
+    }
+
+    /// Anchors for a new link between two nodes after `align_anchors`, when `snap_links` is on.
+    pub fn link_anchors(&self, from: &Node, from_offset: (u16, u16), to: &Node, to_offset: (u16, u16)) -> ((u16, u16), (u16, u16)) {
+        if self.snap_links { align_anchors(from, from_offset, to, to_offset) } else { (from_offset, to_offset) }
+    }
+
+    /// Adds a shape of its type's default size (grown to fit `text`) at `x`, `y`. Returns its id.
+    pub fn add_node(&mut self, shape: ShapeType, x: i32, y: i32, text: &str) -> usize {
+        let id = self.next_id();
+        let (width, height) = shape.default_size();
+        let mut node = Node { id, shape, x, y, width, height, text: clean_label(text, self.tab_width), selected: false, locked: false, group: None, style: NodeStyle::Plain, shadow: false };
+        node.fit_to_text();
+        self.nodes.push(node);
+        id
+    }
+
+    /// Links two shapes on the sides facing each other, as finishing a keyboard link does.
+    /// Returns false if either id is missing or both are the same shape.
+    pub fn connect(&mut self, from_id: usize, to_id: usize, has_arrow: bool) -> bool {
+        if from_id == to_id { return false; }
+        let (Some(from), Some(to)) = (self.nodes.iter().find(|n| n.id == from_id), self.nodes.iter().find(|n| n.id == to_id)) else {
+            return false;
+        };
+        let (from_offset, to_offset) = facing_anchors(from, to);
+        let (from_offset, to_offset) = self.link_anchors(from, from_offset, to, to_offset);
+        self.connections.push(Connection {
+            from_id,
+            from_offset,
+            to: Endpoint::NodeAnchor { to_id, to_offset },
+            has_arrow,
+            weight: Weight::Thin,
+            bend: Bend::Middle,
+            bidirectional: false,
+            label: String::new(),
+        });
+        true
+    }
+
+    /// Finishes a link dragged out of `from_id` at `from_offset` and released at `(x, y)`: it goes
+    /// to the shape there (see `node_at`), entering on the side facing where it leaves the source.
+    /// Returns the target's id, or `None` (adding nothing) when no other shape is under the point.
+    pub fn drop_link(&mut self, from_id: usize, from_offset: (u16, u16), x: i32, y: i32) -> Option<usize> {
+        let target = self.node_at(x, y).filter(|n| n.id != from_id)?;
+        let source = self.nodes.iter().find(|n| n.id == from_id);
+        let (fx, fy) = source.map_or((x, y), |n| n.point_at(from_offset));
+        let to_offset = target.anchor_toward(fx, fy);
+        let (from_offset, to_offset) = match source {
+            Some(src) => self.link_anchors(src, from_offset, target, to_offset),
+            None => (from_offset, to_offset),
+        };
+        let to_id = target.id;
+        self.connections.push(Connection {
+            from_id,
+            from_offset,
+            to: Endpoint::NodeAnchor { to_id, to_offset },
+            has_arrow: true,
+            weight: Weight::Thin,
+            bend: Bend::Middle,
+            bidirectional: false,
+            label: String::new(),
+        });
+        Some(to_id)
+    }
+
+    /// Makes `id` the source of a keyboard connection, replacing any pending one.
+    pub fn start_connection(&mut self, id: usize, arrow: bool) -> Option<String> {
+        let node = self.nodes.iter().find(|n| n.id == id)?;
+        let name = node.text.split_whitespace().next().unwrap_or("Node");
+        let replaced = self.connection_source_id.is_some_and(|old| old != id);
+        let kind = if arrow { "Arrow" } else { "Connector" };
+        let msg = if replaced {
+            format!("{} source replaced: {}. Tab to target, Enter to finish.", kind, name)
+        } else {
+            format!("{} source: {}. Tab to target, Enter to finish.", kind, name)
+        };
+        self.connection_source_id = Some(id);
+        self.connection_source_offset = None;
+        self.connection_has_arrow = arrow;
+        self.click_connect = false;
+        Some(msg)
+    }
+
+    /// Names the shape a pending connection starts from, if any.
+    pub fn pending_source_name(&self) -> Option<&str> {
+        let id = self.connection_source_id?;
+        let node = self.nodes.iter().find(|n| n.id == id)?;
+        Some(node.text.split_whitespace().next().unwrap_or("Node"))
+    }
+
+    /// Records a status message in the log unless it repeats the latest entry.
+    pub fn log_status(&mut self, msg: &str) {
+        if msg.is_empty() || self.last_logged.as_deref() == Some(msg) { return; }
+        let secs = self.started_at.elapsed().as_secs();
+        self.message_log.push_back(format!("[{:02}:{:02}] {}", secs / 60, secs % 60, msg));
+        if self.message_log.len() > MESSAGE_LOG_LEN {
+            self.message_log.pop_front();
+        }
+        self.last_logged = Some(msg.to_string());
+    }
+
+    /// Rebuilds the spatial index if the diagram was edited since it was last built. Cheap to
+    /// call every frame; layout changes that skip `edit` aren't picked up.
+    pub fn refresh_index(&mut self) {
+        if self.spatial.built_at == Some(self.edits) { return; }
+        let counts = (self.nodes.len(), self.connections.len());
+        let mut index = SpatialIndex { built_at: Some(self.edits), counts, ..SpatialIndex::default() };
+        for (i, n) in self.nodes.iter().enumerate() {
+            let far = n.point_at((n.width.saturating_sub(1), n.height.saturating_sub(1)));
+            SpatialIndex::insert(&mut index.nodes, (n.x, n.y), far, i);
+        }
+        for (i, c) in self.connections.iter().enumerate() {
+            let Some(segments) = c.route(&self.nodes) else { continue };
+            // Widened by the pick tolerance so near misses still find the connection
+            let (x1, y1, x2, y2) = segments.iter().fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |(x1, y1, x2, y2), &((ax, ay), (bx, by))| {
+                (x1.min(ax).min(bx), y1.min(ay).min(by), x2.max(ax).max(bx), y2.max(ay).max(by))
+            });
+            let tolerance = PICK_TOLERANCE as i32;
+            let near = (x1 - tolerance, y1 - tolerance);
+            let far = (x2 + tolerance, y2 + tolerance);
+            SpatialIndex::insert(&mut index.connections, near, far, i);
+        }
+        self.spatial = index;
+    }
+
+    /// Index candidates for a hit test at the point. Falls back to every index when shapes or
+    /// links were added or removed since the last refresh (earlier in the same event).
+    fn hit_candidates(&self, x: i32, y: i32, nodes: bool) -> Vec<usize> {
+        if self.spatial.counts != (self.nodes.len(), self.connections.len()) {
+            return (0..if nodes { self.nodes.len() } else { self.connections.len() }).collect();
+        }
+        let buckets = if nodes { &self.spatial.nodes } else { &self.spatial.connections };
+        SpatialIndex::candidates(buckets, x, y).to_vec()
+    }
+
+    /// Index in `nodes` of the node under the point: the topmost shape, else the innermost Frame.
+    /// Shapes win over the frames around them whatever the drawing order, and clicking a frame's
+    /// empty interior still picks the frame.
+    pub fn node_index_at(&self, x: i32, y: i32) -> Option<usize> {
+        let hits: Vec<usize> = self.hit_candidates(x, y, true).into_iter()
+            .filter(|&i| self.nodes[i].contains(x, y))
+            .collect();
+        hits.iter().rev().copied().find(|&i| self.nodes[i].shape != ShapeType::Frame).or_else(|| {
+            hits.iter().copied()
+                .min_by_key(|&i| (self.nodes[i].width as u32 * self.nodes[i].height as u32, std::cmp::Reverse(i)))
+        })
+    }
+
+    /// The node under the point (see `node_index_at`).
+    pub fn node_at(&self, x: i32, y: i32) -> Option<&Node> {
+        self.node_index_at(x, y).map(|i| &self.nodes[i])
+    }
+
+    /// The connection to pick for a click at the point: the topmost one running through it, so
+    /// overlapping edges stay selectable, else the closest within `PICK_TOLERANCE` cells.
+    pub fn connection_near(&self, x: i32, y: i32) -> Option<usize> {
+        let candidates = self.hit_candidates(x, y, false);
+        if let Some(&idx) = candidates.iter().rev().find(|&&i| self.connections[i].contains(x, y, &self.nodes)) {
+            return Some(idx);
+        }
+        candidates.into_iter()
+            .filter_map(|i| Some((self.connections[i].distance_to(x, y, &self.nodes)?, i)))
+            .filter(|&(d, _)| d <= PICK_TOLERANCE as u32)
+            .min_by_key(|&(d, i)| (d, std::cmp::Reverse(i)))
+            .map(|(_, i)| i)
+    }
+
+    /// The topmost node under the point, else the topmost connection, else bare canvas.
+    pub fn target_at(&self, x: i32, y: i32) -> MenuTarget {
+        if let Some(node) = self.node_at(x, y) {
+            MenuTarget::Node(node.id)
+        } else if let Some(idx) = self.connection_near(x, y) {
+            MenuTarget::Connection(idx)
+        } else {
+            MenuTarget::Canvas
+        }
+    }
+
+    /// Each node's id and size, for spotting resizes with `reanchor_resized`.
+    pub fn node_sizes(&self) -> Vec<(usize, u16, u16)> {
+        self.nodes.iter().map(|n| (n.id, n.width, n.height)).collect()
+    }
+
+    /// Keeps connection anchors on the border of every node whose size differs from `before`,
+    /// re-centering them on the side they were attached to.
+    pub fn reanchor_resized(&mut self, before: &[(usize, u16, u16)]) {
+        for &(id, old_w, old_h) in before {
+            let Some(node) = self.nodes.iter().find(|n| n.id == id) else { continue };
+            if (node.width, node.height) == (old_w, old_h) { continue; }
+            for c in &mut self.connections {
+                if c.from_id == id {
+                    c.from_offset = node.reanchor(c.from_offset, old_w, old_h);
+                }
+                if let Endpoint::NodeAnchor { to_id, to_offset } = &mut c.to
+                    && *to_id == id {
+                    *to_offset = node.reanchor(*to_offset, old_w, old_h);
+                }
+            }
+        }
+    }
+
+    /// Changes the shape of every selected, unlocked node in place, keeping id, position, text
+    /// and connections. Nodes grow to the new shape's minimum size; Text nodes refit their label.
+    /// Returns how many nodes changed.
+    pub fn convert_selected(&mut self, shape: ShapeType) -> usize {
+        let (min_w, min_h) = shape.min_size();
+        let mut count = 0;
+        for n in self.nodes.iter_mut().filter(|n| n.selected && !n.locked && n.shape != shape) {
+            n.shape = shape;
+            n.width = n.width.max(min_w);
+            n.height = n.height.max(min_h);
+            n.fit_to_text();
+            count += 1;
+        }
+        count
+    }
+
+    /// Moves and sizes node `id` as typed at the geometry prompt. Sizes below the shape's minimum
+    /// are raised to it. Locked shapes, and sizes for Text shapes (which fit their label), are
+    /// refused with the reason and leave the node as it was.
+    ///
+    /// ```
+    /// use dxgmr::model::{AppState, Geometry, ShapeType};
+    ///
+    /// let mut state = AppState::new(String::from("Exact"));
+    /// let id = state.add_node(ShapeType::Box, 0, 0, "Here");
+    /// state.apply_geometry(id, Geometry::parse("x=10 y=4 w=1").unwrap()).unwrap();
+    /// let node = &state.nodes[0];
+    /// assert_eq!((node.x, node.y, node.width), (10, 4, 3));
+    /// ```
+    pub fn apply_geometry(&mut self, id: usize, geometry: Geometry) -> Result<(), String> {
+        let node = self.nodes.iter_mut().find(|n| n.id == id).ok_or("That shape is gone")?;
+        if node.locked {
+            return Err(String::from("Shape is locked"));
+        }
+        if !node.shape.resizable() && (geometry.width.is_some() || geometry.height.is_some()) {
+            return Err(String::from("Text shapes fit their text; leave out w and h"));
+        }
+        let (min_w, min_h) = node.shape.min_size();
+        node.x = geometry.x.unwrap_or(node.x);
+        node.y = geometry.y.unwrap_or(node.y);
+        node.width = geometry.width.map_or(node.width, |w| w.max(min_w));
+        node.height = geometry.height.map_or(node.height, |h| h.max(min_h));
+        Ok(())
+    }
+
+    /// Yanks the look of the first selected node into `style_buffer`. Returns false if nothing
+    /// is selected.
+    pub fn copy_style(&mut self) -> bool {
+        let Some(node) = self.nodes.iter().find(|n| n.selected) else { return false; };
+        self.style_buffer = Some(StyleSnapshot::of(node));
+        true
+    }
+
+    /// Gives every selected, unlocked node the look in `style_buffer`, leaving id, position,
+    /// text and connections alone. A shadow only sticks to boxes, and Text nodes keep sizing to
+    /// their label. Returns how many nodes were restyled.
+    ///
+    /// ```
+    /// use dxgmr::model::{AppState, NodeStyle, ShapeType};
+    ///
+    /// let mut state = AppState::new(String::from("Painter"));
+    /// let source = state.add_node(ShapeType::Diamond, 0, 0, "Look");
+    /// let target = state.add_node(ShapeType::Box, 30, 4, "Keep me");
+    /// let node = state.nodes.iter_mut().find(|n| n.id == source).unwrap();
+    /// (node.style, node.width, node.height, node.selected) = (NodeStyle::Double, 24, 9, true);
+    /// assert!(state.copy_style());
+    ///
+    /// for n in &mut state.nodes { n.selected = n.id == target; }
+    /// assert_eq!(state.paste_style(), 1);
+    /// let node = state.nodes.iter().find(|n| n.id == target).unwrap();
+    /// assert_eq!((node.shape, node.style, node.width, node.height), (ShapeType::Diamond, NodeStyle::Double, 24, 9));
+    /// assert_eq!((node.x, node.y, node.text.as_str()), (30, 4, "Keep me"));
+    /// ```
+    pub fn paste_style(&mut self) -> usize {
+        let Some(snapshot) = self.style_buffer else { return 0; };
+        let (min_w, min_h) = snapshot.shape.min_size();
+        let mut count = 0;
+        for n in self.nodes.iter_mut().filter(|n| n.selected && !n.locked) {
+            n.shape = snapshot.shape;
+            n.style = snapshot.style;
+            n.shadow = snapshot.shadow && snapshot.shape == ShapeType::Box;
+            n.width = snapshot.width.max(min_w);
+            n.height = snapshot.height.max(min_h);
+            n.fit_to_text();
+            count += 1;
+        }
+        count
+    }
+
+    /// The free position nearest to `(x, y)` for a new `shape` of the given size, searching
+    /// outward ring by ring. Frames are containers, so other shapes may land inside them.
+    pub fn free_spot(&self, shape: ShapeType, x: i32, y: i32, width: u16, height: u16) -> Point {
+        let fits = |cx: i32, cy: i32| {
+            !self.nodes.iter()
+                .filter(|n| shape == ShapeType::Frame || n.shape != ShapeType::Frame)
+                .any(|n| cx < n.x + n.width as i32 && cx + width as i32 > n.x
+                    && cy < n.y + n.height as i32 && cy + height as i32 > n.y)
+        };
+        for r in 0..=MAX_PLACEMENT_SEARCH as i32 {
+            let ring = (-r..=r).flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+                .filter(|(dx, dy)| dx.abs().max(dy.abs()) == r)
+                .filter(|&(dx, dy)| fits(x + dx, y + dy));
+            // Rows count double so the search prefers sliding sideways over stacking
+            if let Some((dx, dy)) = ring.min_by_key(|(dx, dy)| dx * dx + 4 * dy * dy) {
+                return (x + dx, y + dy);
+            }
+        }
+        (x, y)
+    }
+
+    /// IDs of every node sharing a group with `id`, including `id` itself.
+    pub fn group_members(&self, id: usize) -> Vec<usize> {
+        match self.nodes.iter().find(|n| n.id == id).and_then(|n| n.group) {
+            Some(group) => self.nodes.iter().filter(|n| n.group == Some(group)).map(|n| n.id).collect(),
+            None => vec![id],
+        }
+    }
+
+    /// Puts every selected node into a fresh group. Needs at least two nodes;
+    /// returns how many were grouped.
+    pub fn group_selected(&mut self) -> Option<usize> {
+        let count = self.nodes.iter().filter(|n| n.selected).count();
+        if count < 2 { return None; }
+        let group = self.nodes.iter().filter_map(|n| n.group).max().map_or(1, |g| g + 1);
+        for n in self.nodes.iter_mut().filter(|n| n.selected) {
+            n.group = Some(group);
+        }
+        Some(count)
+    }
+
+    /// Removes the selected nodes from their groups, returning how many were grouped.
+    /// A group left with a single member is dissolved too.
+    pub fn ungroup_selected(&mut self) -> usize {
+        let mut count = 0;
+        for n in self.nodes.iter_mut().filter(|n| n.selected && n.group.is_some()) {
+            n.group = None;
+            count += 1;
+        }
+        let lonely: Vec<usize> = self.nodes.iter()
+            .filter_map(|n| n.group)
+            .filter(|&g| self.nodes.iter().filter(|n| n.group == Some(g)).count() == 1)
+            .collect();
+        for n in self.nodes.iter_mut().filter(|n| n.group.is_some_and(|g| lonely.contains(&g))) {
+            n.group = None;
+        }
+        count
+    }
+
+    /// Shrinks or grows a Frame to tightly enclose the nodes inside it, leaving `FRAME_MARGIN`
+    /// cells between its border and the content. Returns false (frame untouched) if it's empty.
+    pub fn fit_frame_to_contents(&mut self, frame_id: usize) -> bool {
+        let inside = self.nodes_in_frame(frame_id);
+        let members = self.nodes.iter().filter(|n| inside.contains(&n.id));
+        let (min_x, min_y, max_x, max_y) = members.fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |(x0, y0, x1, y1), n| {
+            (x0.min(n.x), y0.min(n.y), x1.max(n.x + n.width as i32), y1.max(n.y + n.height as i32))
+        });
+        if inside.is_empty() { return false; }
+        let Some(frame) = self.nodes.iter_mut().find(|n| n.id == frame_id) else { return false; };
+        // One cell for the border itself, plus the margin
+        let pad = FRAME_MARGIN as i32 + 1;
+        frame.x = min_x - pad;
+        frame.y = min_y - pad;
+        frame.width = (max_x + pad - frame.x).min(u16::MAX as i32) as u16;
+        frame.height = (max_y + pad - frame.y).min(u16::MAX as i32) as u16;
+        true
+    }
+
+    /// Nudges overlapping shapes apart until none overlap. Each overlapping pair is separated
+    /// along the axis needing the smaller shift by moving the shape further along that axis, so
+    /// the arrangement is kept. Frames are containers and stay put; a locked shape holds still
+    /// and the other one moves back instead. Returns how many shapes moved: a diagram without
+    /// overlaps is left exactly as it is.
+    pub fn tidy(&mut self) -> usize {
+        let shapes: Vec<usize> = (0..self.nodes.len()).filter(|&i| self.nodes[i].shape != ShapeType::Frame).collect();
+        let before: Vec<Point> = self.nodes.iter().map(|n| (n.x, n.y)).collect();
+        for _ in 0..TIDY_MAX_PASSES {
+            let mut moved = false;
+            for (k, &a) in shapes.iter().enumerate() {
+                for &b in &shapes[k + 1..] {
+                    let (p, q) = (&self.nodes[a], &self.nodes[b]);
+                    let overlap_x = (p.x + p.width as i32).min(q.x + q.width as i32) - p.x.max(q.x);
+                    let overlap_y = (p.y + p.height as i32).min(q.y + q.height as i32) - p.y.max(q.y);
+                    if overlap_x <= 0 || overlap_y <= 0 { continue; }
+                    let horizontal = overlap_x <= overlap_y;
+                    let overlap = if horizontal { overlap_x } else { overlap_y };
+                    // Doubled centres, to compare them without rounding
+                    let centre = |n: &Node| if horizontal { 2 * n.x as i64 + n.width as i64 } else { 2 * n.y as i64 + n.height as i64 };
+                    let (ahead, behind) = if centre(q) >= centre(p) { (b, a) } else { (a, b) };
+                    let (mover, forward) = if !self.nodes[ahead].locked {
+                        (ahead, true)
+                    } else if !self.nodes[behind].locked {
+                        (behind, false)
+                    } else {
+                        continue;
+                    };
+                    let node = &mut self.nodes[mover];
+                    let pos = if horizontal { &mut node.x } else { &mut node.y };
+                    if forward {
+                        *pos += overlap;
+                    } else {
+                        *pos -= overlap;
+                    }
+                    moved = true;
+                }
+            }
+            if !moved { break; }
+        }
+        self.nodes.iter().zip(&before).filter(|(n, pos)| (n.x, n.y) != **pos).count()
+    }
+
+    /// Snaps a candidate position for node `id` so that its left/center/right (or top/middle/bottom)
+    /// lines up with another node's when within one cell. Nodes in `exclude` are ignored.
+    /// Returns the adjusted position and the guide column/row that was snapped to.
+    pub fn snap_position(&self, id: usize, exclude: &[usize], x: i32, y: i32) -> (Point, (Option<i32>, Option<i32>)) {
+        let Some(node) = self.nodes.iter().find(|n| n.id == id) else {
+            return ((x, y), (None, None));
+        };
+        let anchors = |start: i32, len: u16| [start, start + (len / 2) as i32, start + len.saturating_sub(1) as i32];
+
+        let mut best_x: Option<(i32, i32)> = None;
+        let mut best_y: Option<(i32, i32)> = None;
+        for other in self.nodes.iter().filter(|n| n.id != id && !exclude.contains(&n.id)) {
+            for mine in anchors(x, node.width) {
+                for theirs in anchors(other.x, other.width) {
+                    let diff = theirs - mine;
+                    if diff.abs() <= 1 && best_x.is_none_or(|(d, _)| diff.abs() < d.abs()) {
+                        best_x = Some((diff, theirs));
+                    }
+                }
+            }
+            for mine in anchors(y, node.height) {
+                for theirs in anchors(other.y, other.height) {
+                    let diff = theirs - mine;
+                    if diff.abs() <= 1 && best_y.is_none_or(|(d, _)| diff.abs() < d.abs()) {
+                        best_y = Some((diff, theirs));
+                    }
+                }
+            }
+        }
+
+        let snapped_x = best_x.map_or(x, |(d, _)| x + d);
+        let snapped_y = best_y.map_or(y, |(d, _)| y + d);
+        ((snapped_x, snapped_y), (best_x.map(|(_, g)| g), best_y.map(|(_, g)| g)))
+    }
+
+    /// Moves the given nodes by the same delta. The world has no edge, so nothing is clamped and
+    /// the group always keeps its shape.
+    pub fn move_nodes(&mut self, ids: &[usize], dx: i32, dy: i32) {
+        for n in self.nodes.iter_mut().filter(|n| ids.contains(&n.id) && !n.locked) {
+            n.x = n.x.saturating_add(dx);
+            n.y = n.y.saturating_add(dy);
+        }
+    }
+}
+
+/// Default spacing of tab stops in labels, in columns.
+pub const TAB_WIDTH: u16 = 4;
+
+fn default_tab_width() -> u16 {
+    TAB_WIDTH
+}
+
+/// Appends typed or pasted input to a label, cleaned up: a tab pads to the next multiple of
+/// `tab_width` columns on its line (so pasted code keeps its columns), CR/CRLF become '\n',
+/// and every other control character is dropped. The char grid renderer can't place them sensibly.
+///
+/// ```
+/// let mut text = String::from("ab");
+/// dxgmr::model::append_label(&mut text, "\tc\td\n\te", 4);
+/// assert_eq!(text, "ab  c   d\n    e");
+/// ```
+pub fn append_label(text: &mut String, input: &str, tab_width: u16) {
+    let tab_width = tab_width.max(1);
+    let mut column = display_width(text.rsplit('\n').next().unwrap_or(""));
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\t' => {
+                let spaces = tab_width - column % tab_width;
+                text.extend(std::iter::repeat_n(' ', spaces as usize));
+                column = column.saturating_add(spaces);
+            }
+            '\r' | '\n' => {
+                if c == '\r' && chars.peek() == Some(&'\n') { chars.next(); }
+                text.push('\n');
+                column = 0;
+            }
+            c if c.is_control() => {}
+            c => {
+                text.push(c);
+                column = column.saturating_add(UnicodeWidthChar::width(c).unwrap_or(0) as u16);
+            }
         }
     }
 }
 
+/// `text` with its tabs expanded and control characters cleaned up as if typed in afresh.
+pub fn clean_label(text: &str, tab_width: u16) -> String {
+    let mut out = String::with_capacity(text.len());
+    append_label(&mut out, text, tab_width);
+    out
+}
+
+/// Name stem a diagram titled `title` is saved under: characters that are unsafe in a file
+/// name on some system (path separators, `:*?"<>|`, control characters) become `_`, and
+/// leading or trailing spaces and dots are dropped. Anything else, Unicode included, is kept.
+/// An empty result falls back to `Untitled Diagram`.
+///
+/// ```
+/// use dxgmr::model::sanitize_filename;
+///
+/// assert_eq!(sanitize_filename("Login flow"), "Login flow");
+/// assert_eq!(sanitize_filename("client/server"), "client_server");
+/// assert_eq!(sanitize_filename(r"C:\temp\plan"), "C__temp_plan");
+/// assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+/// assert_eq!(sanitize_filename("Ablauf für Zahlungen → Bank 銀行"), "Ablauf für Zahlungen → Bank 銀行");
+/// assert_eq!(sanitize_filename("What? <draft>"), "What_ _draft_");
+/// assert_eq!(sanitize_filename(""), "Untitled Diagram");
+/// assert_eq!(sanitize_filename(" .. "), "Untitled Diagram");
+/// ```
+pub fn sanitize_filename(title: &str) -> String {
+    let safe: String = title.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let trimmed = safe.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if trimmed.is_empty() { String::from("Untitled Diagram") } else { trimmed.to_string() }
+}
+
+/// `time` as an RFC 3339 UTC timestamp to the second, e.g. for `Diagram::modified`. Times
+/// before 1970 come out as the epoch.
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use dxgmr::model::rfc3339_utc;
+///
+/// assert_eq!(rfc3339_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+/// assert_eq!(rfc3339_utc(UNIX_EPOCH + Duration::from_secs(1_709_210_096)), "2024-02-29T12:34:56Z");
+/// ```
+pub fn rfc3339_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since the epoch, in 400-year eras counted from 0000-03-01
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+/// Number of terminal columns `text` occupies (wide CJK/emoji glyphs count as two).
+pub fn display_width(text: &str) -> u16 {
+    UnicodeWidthStr::width(text) as u16
+}
+
+/// The longest prefix of `text` that fits in `max_width` columns, cut at a grapheme boundary.
+pub fn truncate_to_width(text: &str, max_width: u16) -> &str {
+    let mut used = 0;
+    for (idx, g) in text.grapheme_indices(true) {
+        used += display_width(g);
+        if used > max_width {
+            return &text[..idx];
+        }
+    }
+    text
+}
+
+/// Wraps `text` to `max_width` columns at spaces, keeping blank lines. Runs of spaces within a
+/// line are kept as typed, so labels can line up little columns; only the spaces a line breaks
+/// at are dropped. Words too long for a line are broken between graphemes, with a `-` marking
+/// each break.
 pub fn wrap_text(text: &str, max_width: u16) -> Vec<String> {
     if max_width == 0 { return Vec::new(); }
+    wrap_text_varying(text, &[max_width], true)
+}
+
+/// Like `wrap_text`, but line `i` may be at most `widths[i]` wide; the last width repeats for
+/// any further lines. Used for shapes whose interior isn't rectangular. Without `hyphenate`,
+/// over-long words are broken bare.
+pub fn wrap_text_varying(text: &str, widths: &[u16], hyphenate: bool) -> Vec<String> {
+    let width_at = |line: usize| widths.get(line).or(widths.last()).copied().unwrap_or(1).max(1) as usize;
     let mut all_lines = Vec::new();
     
     for paragraph in text.split('\n') {
         let mut current_line = String::new();
-        let mut paragraph_lines = Vec::new();
+        let mut paragraph_lines: Vec<String> = Vec::new();
         
         for word in paragraph.split_inclusive(' ') {
-            let is_too_long = (current_line.len() + word.len()) > max_width as usize;
+            // A word's trailing space may hang past the edge; only its glyphs need to fit
+            let max_width = width_at(all_lines.len() + paragraph_lines.len());
+            let glyphs = word.trim_end();
+            let is_too_long = !glyphs.is_empty() && (display_width(&current_line) + display_width(glyphs)) as usize > max_width;
             
             if is_too_long && !current_line.is_empty() {
-                paragraph_lines.push(current_line);
+                // The spaces the line broke at aren't part of either line
+                paragraph_lines.push(current_line.trim_end().to_string());
                 current_line = String::new();
             }
             
+            // Break over-long words between graphemes, always making progress
             let mut w = word;
-            while w.len() > max_width as usize {
-                let (part, rest) = w.split_at(max_width as usize);
-                paragraph_lines.push(part.to_string());
+            while display_width(w.trim_end()) as usize > width_at(all_lines.len() + paragraph_lines.len()) {
+                let limit = width_at(all_lines.len() + paragraph_lines.len()) as u16;
+                let hyphen = hyphenate && limit >= 2;
+                let mut split = truncate_to_width(w, if hyphen { limit - 1 } else { limit }).len();
+                let mut part_end = if hyphen { "-" } else { "" };
+                if split == 0 {
+                    split = w.graphemes(true).next().map_or(w.len(), |g| g.len());
+                    part_end = "";
+                }
+                let (part, rest) = w.split_at(split);
+                paragraph_lines.push(format!("{}{}", part, part_end));
                 w = rest;
             }
             current_line.push_str(w);
@@ -186,3 +2214,354 @@ pub fn wrap_text(text: &str, max_width: u16) -> Vec<String> {
 
     all_lines
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A `side` x `side` grid of boxes, each linked to its right-hand and lower neighbours.
+    fn grid(side: i32) -> AppState {
+        let mut state = AppState::new(String::from("Grid"));
+        for row in 0..side {
+            for col in 0..side {
+                state.add_node(ShapeType::Box, col * 30, row * 10, &format!("Node {}", row * side + col));
+            }
+        }
+        for id in 1..=(side * side) as usize {
+            if id % side as usize != 0 { state.connect(id, id + 1, true); }
+            if id + (side as usize) <= (side * side) as usize { state.connect(id, id + side as usize, true); }
+        }
+        state.edit();
+        state
+    }
+
+    /// Average time per call of `f` over `rounds` calls.
+    fn time_per_call(rounds: u32, mut f: impl FnMut(u32)) -> Duration {
+        let start = Instant::now();
+        for i in 0..rounds { f(i); }
+        start.elapsed() / rounds
+    }
+
+    /// Timing of hit tests on a few hundred shapes, with and without the spatial index. Run with
+    /// `cargo test --release -- --ignored --nocapture index_timing`.
+    #[test]
+    #[ignore]
+    fn index_timing() {
+        let mut state = grid(20);
+        let (nodes, links) = (state.nodes.len(), state.connections.len());
+        // Points spread over the whole grid, the same for every run
+        let point = |i: u32| ((i.wrapping_mul(7919) % 600) as i32, (i.wrapping_mul(104_729) % 200) as i32);
+
+        let build = time_per_call(20, |_| {
+            state.spatial = SpatialIndex::default();
+            state.refresh_index();
+        });
+        let idle = time_per_call(10_000, |_| state.refresh_index());
+        let indexed = time_per_call(10_000, |i| {
+            let (x, y) = point(i);
+            std::hint::black_box((state.node_at(x, y), state.connection_near(x, y)));
+        });
+        let linear = time_per_call(10_000, |i| {
+            let (x, y) = point(i);
+            let node = state.nodes.iter().rposition(|n| n.contains(x, y));
+            let link = state.connections.iter().rposition(|c| c.distance_to(x, y, &state.nodes).is_some_and(|d| d <= PICK_TOLERANCE as u32));
+            std::hint::black_box((node, link));
+        });
+        println!("{} nodes, {} links: build {:?}, idle refresh {:?}, indexed hit test {:?}, linear scan {:?}", nodes, links, build, idle, indexed, linear);
+        assert!(indexed < linear);
+    }
+
+    /// A plain box at `(x, y)` of the given size.
+    fn node(x: i32, y: i32, width: u16, height: u16) -> Node {
+        let mut state = AppState::new(String::new());
+        state.add_node(ShapeType::Box, x, y, "");
+        let mut node = state.nodes.remove(0);
+        (node.width, node.height) = (width, height);
+        node
+    }
+
+    #[test]
+    fn intersects_rectangles() {
+        // Covers columns 10..=19 and rows 10..=14
+        let n = node(10, 10, 10, 5);
+        let cases = [
+            ("inside the node", (12, 11), (14, 12), true),
+            ("around the node", (0, 0), (30, 30), true),
+            ("partial overlap", (15, 12), (25, 20), true),
+            ("sharing the right column", (19, 0), (25, 30), true),
+            ("sharing the bottom row", (0, 14), (30, 20), true),
+            ("touching the right edge", (20, 10), (25, 14), false),
+            ("touching the top edge", (10, 5), (19, 9), false),
+            ("disjoint", (30, 30), (40, 40), false),
+            ("reversed corners", (25, 20), (15, 12), true),
+            ("reversed corners, disjoint", (9, 9), (0, 0), false),
+        ];
+        for (name, a, b, expected) in cases {
+            assert_eq!(n.intersects(a, b), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn facing_anchors_by_quadrant() {
+        // Center (5, 2); anchors (9, 2) right, (0, 2) left, (5, 4) bottom, (5, 0) top
+        let from = node(0, 0, 10, 5);
+        let (right, left, bottom, top) = ((9, 2), (0, 2), (5, 4), (5, 0));
+        let cases = [
+            ("right", (40, 0), right, left),
+            ("left", (-40, 0), left, right),
+            ("below", (0, 20), bottom, top),
+            ("above", (0, -20), top, bottom),
+            // Rows count double, so a 40 x 20 diagonal is steep and a 60 x 10 one shallow
+            ("steep, below right", (40, 20), bottom, top),
+            ("shallow, below right", (60, 10), right, left),
+            ("steep, above left", (-40, -20), top, bottom),
+            ("shallow, above left", (-60, -10), left, right),
+            ("steep, below left", (-40, 20), bottom, top),
+            ("shallow, above right", (60, -10), right, left),
+        ];
+        for (name, (x, y), from_offset, to_offset) in cases {
+            assert_eq!(facing_anchors(&from, &node(x, y, 10, 5)), (from_offset, to_offset), "{}", name);
+        }
+    }
+
+    #[test]
+    fn align_anchors_near_aligned_pairs() {
+        let a = node(0, 0, 10, 5);
+        let cases = [
+            // Side by side, one row apart: the target end moves onto the source's row
+            ("target moves", node(20, 1, 10, 5), (9, 2), (0, 2), ((9, 2), (0, 1))),
+            // The source's row is the target's corner, so the source end moves instead
+            ("source moves", node(20, 2, 10, 3), (9, 2), (0, 1), ((9, 3), (0, 1))),
+            ("already aligned", node(20, 0, 10, 5), (9, 2), (0, 2), ((9, 2), (0, 2))),
+            ("two rows apart", node(20, 2, 10, 5), (9, 2), (0, 2), ((9, 2), (0, 2))),
+            // Stacked, one column apart
+            ("stacked, target moves", node(1, 10, 10, 5), (5, 4), (5, 0), ((5, 4), (4, 0))),
+            ("stacked, two columns apart", node(2, 10, 10, 5), (5, 4), (5, 0), ((5, 4), (5, 0))),
+            // A side anchor to a top anchor already bends, so neither end moves
+            ("mixed sides", node(20, 10, 10, 5), (9, 2), (5, 0), ((9, 2), (5, 0))),
+        ];
+        for (name, b, from_offset, to_offset, expected) in cases {
+            assert_eq!(align_anchors(&a, from_offset, &b, to_offset), expected, "{}", name);
+        }
+    }
+
+    /// A thin arrow from `from_id` at `from_offset` to `to`.
+    fn link(from_id: usize, from_offset: (u16, u16), to: Endpoint) -> Connection {
+        Connection { from_id, from_offset, to, has_arrow: true, weight: Weight::Thin, bend: Bend::Middle, bidirectional: false, label: String::new() }
+    }
+
+    #[test]
+    fn route_legs_leave_the_chosen_sides() {
+        // Far apart on both axes, so no leg of any route has zero length
+        let mut nodes = [node(0, 0, 20, 5), node(60, 30, 20, 5)];
+        nodes[1].id = 2;
+        let (a, b) = (1, 2);
+        // (name, offset, whether the side is the top or bottom)
+        let sides = [("top", (10, 0), true), ("bottom", (10, 4), true), ("left", (0, 2), false), ("right", (19, 2), false)];
+        for (from_name, from_offset, from_vertical) in sides {
+            for (to_name, to_offset, to_vertical) in sides {
+                let mut c = link(a, from_offset, Endpoint::NodeAnchor { to_id: b, to_offset });
+                c.has_arrow = false;
+                let route = c.route(&nodes).unwrap();
+                let (first, last) = (route[0], route[route.len() - 1]);
+                let case = format!("{} to {}", from_name, to_name);
+                assert_eq!(first.0, nodes[0].point_at(from_offset), "{}", case);
+                assert_eq!(last.1, nodes[1].point_at(to_offset), "{}", case);
+                assert!(first.0 != first.1 && last.0 != last.1, "{}", case);
+                assert_eq!(first.0.0 == first.1.0, from_vertical, "{}: first leg", case);
+                assert_eq!(last.0.0 == last.1.0, to_vertical, "{}: last leg", case);
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_swaps_ends() {
+        let mut c = link(1, (9, 2), Endpoint::NodeAnchor { to_id: 2, to_offset: (0, 1) });
+        assert!(c.reverse());
+        assert_eq!((c.from_id, c.from_offset), (2, (0, 1)));
+        assert!(matches!(c.to, Endpoint::NodeAnchor { to_id: 1, to_offset: (9, 2) }));
+
+        let mut free = link(1, (9, 2), Endpoint::FreePoint { to_point: (40, 7) });
+        assert!(!free.reverse());
+        assert_eq!((free.from_id, free.from_offset), (1, (9, 2)));
+        assert!(matches!(free.to, Endpoint::FreePoint { to_point: (40, 7) }));
+    }
+
+    #[test]
+    fn reanchor_resized_keeps_anchors_on_the_border() {
+        let mut state = AppState::new(String::from("Resize"));
+        let a = state.add_node(ShapeType::Box, 0, 0, "A");
+        let b = state.add_node(ShapeType::Box, 40, 0, "B");
+        let c = state.add_node(ShapeType::Box, 0, 20, "C");
+        assert!(state.connect(a, b, true));
+        assert!(state.connect(c, a, true));
+        // A's anchors: the start of the first link and the end of the second
+        let anchors = |state: &AppState| {
+            let Endpoint::NodeAnchor { to_offset, .. } = state.connections[1].to else { unreachable!() };
+            (state.connections[0].from_offset, to_offset)
+        };
+
+        for (width, height, right, bottom) in [(20, 7, (19, 3), (10, 6)), (6, 3, (5, 1), (3, 2))] {
+            let before = state.node_sizes();
+            let node = state.nodes.iter_mut().find(|n| n.id == a).unwrap();
+            (node.width, node.height) = (width, height);
+            state.reanchor_resized(&before);
+            assert_eq!(anchors(&state), (right, bottom), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn wrap_text_cases() {
+        let cases: [(&str, u16, &[&str]); 12] = [
+            ("ab cd", 5, &["ab cd"]),
+            ("ab cd", 4, &["ab", "cd"]),
+            ("abc def", 3, &["abc", "def"]),
+            ("abc def ghi", 7, &["abc def", "ghi"]),
+            ("abcdefgh", 4, &["abc-", "def-", "gh"]),
+            ("ab abcdefgh", 4, &["ab", "abc-", "def-", "gh"]),
+            ("abcdefgh", 1, &["a", "b", "c", "d", "e", "f", "g", "h"]),
+            ("a    b", 10, &["a    b"]),
+            ("a  b  c", 4, &["a  b", "c"]),
+            ("a    b", 3, &["a", "b"]),
+            ("one\n\ntwo", 10, &["one", "", "two"]),
+            ("ab", 0, &[]),
+        ];
+        for (text, width, expected) in cases {
+            assert_eq!(wrap_text(text, width), expected, "{:?} at {}", text, width);
+        }
+    }
+
+    #[test]
+    fn fit_to_text_shrinks_as_characters_are_deleted() {
+        let mut state = AppState::new(String::from("Fit"));
+        let id = state.add_node(ShapeType::Text, 0, 0, "Go\nStatus");
+        let node = state.nodes.iter_mut().find(|n| n.id == id).unwrap();
+        assert_eq!((node.width, node.height), (6, 2));
+        let mut sizes = Vec::new();
+        while node.text.pop().is_some() {
+            node.fit_to_text();
+            sizes.push((node.width, node.height));
+        }
+        // "Go\nStatu" down to "Go\nS", then "Go\n", "Go", "G" and ""; an empty label keeps one cell
+        assert_eq!(sizes, [(5, 2), (4, 2), (3, 2), (2, 2), (2, 2), (2, 2), (2, 1), (1, 1), (1, 1)]);
+
+        node.text = String::from("ab   ");
+        node.fit_to_text();
+        assert_eq!((node.width, node.height), (2, 1));
+    }
+
+    #[test]
+    fn frames_nested_and_overlapping() {
+        let mut state = AppState::new(String::from("Frames"));
+        let mut add = |shape, x, y, width, height| {
+            let id = state.add_node(shape, x, y, "");
+            let node = state.nodes.iter_mut().find(|n| n.id == id).unwrap();
+            (node.width, node.height) = (width, height);
+            id
+        };
+        let outer = add(ShapeType::Frame, 0, 0, 60, 30);
+        let inner = add(ShapeType::Frame, 5, 5, 30, 15);
+        let deep = add(ShapeType::Box, 10, 8, 10, 3);
+        let shallow = add(ShapeType::Box, 40, 5, 10, 3);
+        let straddling = add(ShapeType::Box, 55, 10, 10, 3);
+        // Two frames overlapping, the second smaller; a shape in both goes to the smaller one
+        let wide = add(ShapeType::Frame, 70, 0, 30, 20);
+        let narrow = add(ShapeType::Frame, 80, 5, 25, 15);
+        let in_both = add(ShapeType::Box, 85, 8, 10, 3);
+        let in_wide = add(ShapeType::Box, 72, 2, 6, 3);
+        // Two equal frames overlapping: the one drawn on top wins
+        let under = add(ShapeType::Frame, 0, 40, 30, 10);
+        let over = add(ShapeType::Frame, 10, 40, 30, 10);
+        let tied = add(ShapeType::Box, 15, 43, 10, 3);
+
+        assert_eq!(state.parent_frame(deep), Some(inner));
+        assert_eq!(state.parent_frame(inner), Some(outer));
+        assert_eq!(state.parent_frame(shallow), Some(outer));
+        assert_eq!(state.parent_frame(straddling), None);
+        assert_eq!(state.parent_frame(outer), None);
+        assert_eq!(state.parent_frame(in_both), Some(narrow));
+        assert_eq!(state.parent_frame(in_wide), Some(wide));
+        assert_eq!(state.parent_frame(tied), Some(over));
+
+        assert_eq!(state.nodes_in_frame(outer), [inner, deep, shallow]);
+        assert_eq!(state.nodes_in_frame(inner), [deep]);
+        assert_eq!(state.nodes_in_frame(wide), [in_wide]);
+        assert_eq!(state.nodes_in_frame(narrow), [in_both]);
+        assert!(state.nodes_in_frame(under).is_empty());
+        assert_eq!(state.nodes_in_frame(over), [tied]);
+        // Not a frame
+        assert!(state.nodes_in_frame(deep).is_empty());
+    }
+
+    #[test]
+    fn tidy_separates_overlapping_boxes_once() {
+        let mut state = AppState::new(String::from("Tidy"));
+        let a = state.add_node(ShapeType::Box, 0, 0, "A");
+        let b = state.add_node(ShapeType::Box, 15, 2, "B");
+        let overlapping = |state: &AppState| {
+            let (p, q) = (&state.nodes[0], &state.nodes[1]);
+            q.intersects((p.x, p.y), (p.x + p.width as i32 - 1, p.y + p.height as i32 - 1))
+        };
+        assert!(overlapping(&state));
+
+        // 5 columns of overlap against 3 rows: B moves down, clear of A
+        assert_eq!(state.tidy(), 1);
+        assert!(!overlapping(&state));
+        let position = |id: usize| state.nodes.iter().find(|n| n.id == id).map(|n| (n.x, n.y)).unwrap();
+        assert_eq!((position(a), position(b)), ((0, 0), (15, 5)));
+
+        let before: Vec<Point> = state.nodes.iter().map(|n| (n.x, n.y)).collect();
+        assert_eq!(state.tidy(), 0);
+        assert_eq!(state.nodes.iter().map(|n| (n.x, n.y)).collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    fn drop_link_prefers_a_shape_over_its_frame() {
+        let mut state = AppState::new(String::from("Drop"));
+        // The frame comes first in the Vec, as it does when it's drawn before its contents
+        let frame = state.add_node(ShapeType::Frame, 0, 0, "Group");
+        let inner = state.add_node(ShapeType::Box, 5, 3, "Inner");
+        let source = state.add_node(ShapeType::Box, 50, 3, "Source");
+        state.edit();
+        state.refresh_index();
+
+        assert_eq!(state.drop_link(source, (0, 2), 10, 5), Some(inner));
+        let Endpoint::NodeAnchor { to_id, to_offset } = state.connections[0].to else { unreachable!() };
+        // Entered on the side facing the source
+        assert_eq!((to_id, to_offset), (inner, (19, 2)));
+        // Empty frame interior: the frame takes it
+        assert_eq!(state.drop_link(source, (0, 2), 27, 8), Some(frame));
+        // Back on the source, or on empty canvas: nothing is added
+        assert_eq!(state.drop_link(source, (0, 2), 55, 5), None);
+        assert_eq!(state.drop_link(source, (0, 2), 100, 40), None);
+        assert_eq!(state.connections.len(), 2);
+    }
+
+    fn node_text(state: &AppState, id: usize) -> &str {
+        &state.nodes.iter().find(|n| n.id == id).unwrap().text
+    }
+
+    #[test]
+    fn compact_ids_keeps_links_on_their_nodes() {
+        let mut state = AppState::new(String::from("Ids"));
+        for text in ["A", "B", "C"] { state.add_node(ShapeType::Box, 0, 0, text); }
+        // Ids out of Vec order, with gaps
+        for (node, id) in state.nodes.iter_mut().zip([30, 10, 20]) { node.id = id; }
+        assert!(state.connect(30, 10, true));
+        assert!(state.connect(20, 30, true));
+        state.current_selected_id = Some(30);
+
+        assert_eq!(state.compact_ids(), 3);
+        let mut ids: Vec<usize> = state.nodes.iter().map(|n| n.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 2, 3]);
+        let links: Vec<(&str, &str)> = state.connections.iter().map(|c| match c.to {
+            Endpoint::NodeAnchor { to_id, .. } => (node_text(&state, c.from_id), node_text(&state, to_id)),
+            Endpoint::FreePoint { .. } => panic!("free endpoint"),
+        }).collect();
+        assert_eq!(links, [("A", "B"), ("C", "A")]);
+        assert_eq!(node_text(&state, state.current_selected_id.unwrap()), "A");
+        assert_eq!(state.compact_ids(), 0);
+    }
+}
@@ -0,0 +1,199 @@
+//! PNG export: rasterizes the ASCII export with a built-in 5x7 bitmap font.
+
+use image::{ImageEncoder, ImageResult, Luma};
+use image::codecs::png::PngEncoder;
+use unicode_width::UnicodeWidthChar;
+
+use crate::model::{AppState, Diagram};
+use crate::renderer::write_ascii;
+
+/// Glyph size in font pixels; each cell adds one pixel of spacing on the right and below.
+const GLYPH_W: u32 = 5;
+const GLYPH_H: u32 = 7;
+const CELL_W: u32 = GLYPH_W + 1;
+const CELL_H: u32 = GLYPH_H + 1;
+
+/// Rows of printable ASCII (' '..='~'), most significant of the low five bits leftmost.
+const FONT: [[u8; GLYPH_H as usize]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A], // #
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D], // &
+    [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E], // @
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // '\\'
+    [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E], // ]
+    [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+    [0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F], // a
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1E], // b
+    [0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E], // c
+    [0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F], // d
+    [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E], // e
+    [0x06, 0x09, 0x08, 0x1C, 0x08, 0x08, 0x08], // f
+    [0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x0E], // g
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11], // h
+    [0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x0E], // i
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0C], // j
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12], // k
+    [0x0C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // l
+    [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11], // m
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11], // n
+    [0x00, 0x00, 0x0E, 0x11, 0x11, 0x11, 0x0E], // o
+    [0x00, 0x00, 0x1E, 0x11, 0x1E, 0x10, 0x10], // p
+    [0x00, 0x00, 0x0D, 0x13, 0x0F, 0x01, 0x01], // q
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10], // r
+    [0x00, 0x00, 0x0E, 0x10, 0x0E, 0x01, 0x1E], // s
+    [0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06], // t
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0D], // u
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0A, 0x04], // v
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0A], // w
+    [0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11], // x
+    [0x00, 0x00, 0x11, 0x11, 0x0F, 0x01, 0x0E], // y
+    [0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F], // z
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02], // {
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // |
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], // }
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00], // ~
+];
+
+/// Anything outside printable ASCII is drawn as a hollow box.
+const MISSING: [u8; GLYPH_H as usize] = [0x1F, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1F];
+
+/// Renders the diagram as its ASCII export would show it, as a black-on-white PNG with each
+/// font pixel `scale` pixels square. Like the export it covers the whole diagram: the camera
+/// saved in the file doesn't crop it.
+///
+/// ```
+/// use dxgmr::builder::DiagramBuilder;
+/// use dxgmr::png::to_png;
+///
+/// let mut diagram = DiagramBuilder::new("Pixels").box_at(0, 0, "Hi").box_at(30, 10, "There").build();
+/// let bytes = to_png(&diagram, 2).unwrap();
+/// assert_eq!(&bytes[1..4], b"PNG");
+/// // 50 x 15 cells of 6 x 8 font pixels, doubled
+/// assert_eq!(&bytes[16..24], &[0, 0, 2, 88, 0, 0, 0, 240]);
+///
+/// // Saved while scrolled past the first box: the same picture
+/// diagram.camera_offset = (25, 8);
+/// assert_eq!(to_png(&diagram, 2).unwrap(), bytes);
+/// ```
+pub fn to_png(diagram: &Diagram, scale: u32) -> ImageResult<Vec<u8>> {
+    let state = AppState::from_diagram(diagram.clone());
+    let mut text = Vec::new();
+    write_ascii(&state, state.export_columns(), state.export_rows(), &mut text)?;
+    rasterize(&String::from_utf8_lossy(&text), scale)
+}
+
+/// Draws lines of text as a black-on-white PNG, each font pixel `scale` pixels square.
+/// Zero-width characters (combining marks and the like) take no cell and are skipped.
+fn rasterize(text: &str, scale: u32) -> ImageResult<Vec<u8>> {
+    let scale = scale.max(1);
+    let lines: Vec<&str> = text.lines().collect();
+    let cols = lines.iter().map(|l| l.chars().map(|c| c.width().unwrap_or(0)).sum::<usize>()).max().unwrap_or(0);
+    let width = (cols.max(1) as u32) * CELL_W * scale;
+    let height = (lines.len().max(1) as u32) * CELL_H * scale;
+    let mut img = image::GrayImage::from_pixel(width, height, Luma([255]));
+
+    for (row, line) in lines.iter().enumerate() {
+        let mut col = 0u32;
+        for c in line.chars() {
+            let cells = c.width().unwrap_or(0) as u32;
+            if cells == 0 { continue; }
+            let glyph = match c {
+                ' '..='~' => &FONT[c as usize - 0x20],
+                _ => &MISSING,
+            };
+            for (gy, bits) in glyph.iter().enumerate() {
+                for gx in 0..GLYPH_W {
+                    if bits & (1 << (GLYPH_W - 1 - gx)) == 0 { continue; }
+                    let px = (col * CELL_W + gx) * scale;
+                    let py = (row as u32 * CELL_H + gy as u32) * scale;
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            img.put_pixel(px + dx, py + dy, Luma([0]));
+                        }
+                    }
+                }
+            }
+            col += cells;
+        }
+    }
+
+    let mut out = Vec::new();
+    PngEncoder::new(&mut out).write_image(img.as_raw(), width, height, image::ExtendedColorType::L8)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_width_chars_draw_nothing() {
+        // A combining accent rides on the cell before it instead of boxing over it
+        assert_eq!(rasterize("e\u{301}x", 1).unwrap(), rasterize("ex", 1).unwrap());
+        assert_eq!(rasterize("a\u{200B}b", 1).unwrap(), rasterize("ab", 1).unwrap());
+    }
+
+    #[test]
+    fn wide_chars_take_two_cells() {
+        let wide = image::load_from_memory(&rasterize("中", 1).unwrap()).unwrap();
+        assert_eq!(wide.width(), 2 * CELL_W);
+    }
+}
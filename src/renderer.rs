@@ -1,9 +1,34 @@
-use crate::model::{AppState, Node, ShapeType};
+use std::io::{self, Write};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::model::{display_width, label_spot, route_segments, AppState, Bend, Connection, Corner, Endpoint, GlyphSet, Legend, Node, NodeStyle, Point, Segment, ShapeType, Weight, LEGEND_HEADING};
+
+/// Largest mini-map interior, in cells.
+const MINIMAP_WIDTH: u16 = 24;
+const MINIMAP_HEIGHT: u16 = 8;
+
+/// Rows rendered at a time by `write_ascii`.
+const EXPORT_BAND_ROWS: u16 = 64;
+
+/// Fills the cell to the right of a double-width glyph; skipped when the canvas is printed.
+const WIDE_TAIL: char = '\0';
+
+/// Which way a connection line runs through a cell, so a crossing line can tell it's crossing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stroke {
+    None,
+    Horizontal,
+    Vertical,
+    Both, // Lines cross here
+}
 
 pub struct Canvas {
     pub width: u16,
     pub height: u16,
     pub grid: Vec<Vec<char>>,
+    strokes: Vec<Vec<Stroke>>,
+    origin: (i32, i32), // Drawing coordinates of the top-left cell; anything left of / above it is clipped
 }
 
 impl Canvas {
@@ -12,12 +37,86 @@ impl Canvas {
             width,
             height,
             grid: vec![vec![' '; width as usize]; height as usize],
+            strokes: vec![vec![Stroke::None; width as usize]; height as usize],
+            origin: (0, 0),
+        }
+    }
+
+    /// Grid `(row, column)` of a drawing coordinate, if it falls on the canvas.
+    fn cell(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let col = x - self.origin.0;
+        let row = y - self.origin.1;
+        (col >= 0 && row >= 0 && col < self.width as i32 && row < self.height as i32)
+            .then_some((row as usize, col as usize))
+    }
+
+    /// Each row as saved or copied: wide-glyph tails dropped and trailing spaces trimmed.
+    /// The TUI keeps using `Display`, whose padded rows fill the widget.
+    fn export_lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.grid.iter()
+            .map(|row| row.iter().filter(|&&c| c != WIDE_TAIL).collect::<String>().trim_end().to_string())
+    }
+
+    pub fn set_if_blank(&mut self, x: i32, y: i32, c: char) {
+        if let Some((row, col)) = self.cell(x, y) && self.grid[row][col] == ' ' {
+            self.set(x, y, c);
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, c: char) {
+        if let Some((y, x)) = self.cell(x, y) {
+            let row = &mut self.grid[y];
+            // Never leave half of a wide glyph behind
+            if row[x] == WIDE_TAIL && x > 0 {
+                row[x - 1] = ' ';
+            }
+            if row.get(x + 1) == Some(&WIDE_TAIL) {
+                row[x + 1] = ' ';
+            }
+            row[x] = c;
+        }
+    }
+
+    /// Writes `text` from `(x, y)` rightwards, honouring glyph display widths and stopping before
+    /// any glyph that would reach column `limit_x`.
+    pub fn put_text(&mut self, x: i32, y: i32, text: &str, limit_x: i32) {
+        let right = self.origin.0 + self.width as i32;
+        let mut cx = x;
+        for c in text.chars() {
+            let w = UnicodeWidthChar::width(c).unwrap_or(0) as i32;
+            if w == 0 { continue; }
+            if cx + w > limit_x.min(right) { break; }
+            if w == 2 {
+                // Clear the tail cell first so `set` doesn't mistake it for a stale half-glyph
+                self.set(cx + 1, y, ' ');
+                // A glyph cut off at the left edge leaves just the blank
+                if let Some((row, col)) = self.cell(cx, y) {
+                    self.set(cx, y, c);
+                    self.grid[row][col + 1] = WIDE_TAIL;
+                }
+            } else {
+                self.set(cx, y, c);
+            }
+            cx += w;
         }
     }
 
-    pub fn set(&mut self, x: u16, y: u16, c: char) {
-        if x < self.width && y < self.height {
-            self.grid[y as usize][x as usize] = c;
+    /// Blanks what a shape covers so lines drawn earlier don't show through it. Frames are left
+    /// open: the links between the shapes they hold belong inside them.
+    pub fn clear_interior(&mut self, node: &Node) {
+        match node.shape {
+            ShapeType::Box | ShapeType::Text => {
+                for y in node.y..node.y + node.height as i32 {
+                    for x in node.x..node.x + node.width as i32 { self.set_conn(x, y, ' '); }
+                }
+            }
+            ShapeType::Diamond => {
+                for y in node.y..node.y + node.height as i32 {
+                    let (start, width) = diamond_row_span(node, y);
+                    for x in start..start + width as i32 { self.set_conn(x, y, ' '); }
+                }
+            }
+            ShapeType::Frame => {}
         }
     }
 
@@ -26,38 +125,44 @@ impl Canvas {
         let available_height = node.height;
         let lines = crate::model::wrap_text(&node.text, available_width);
         let total_lines = lines.len() as u16;
-        let start_y = node.y + (available_height.saturating_sub(total_lines)) / 2;
+        let start_y = node.y + ((available_height.saturating_sub(total_lines)) / 2) as i32;
         
         for (i, line) in lines.iter().enumerate().take(available_height as usize) {
-            let ty = start_y + i as u16;
-            let text_start_x = node.x + (available_width.saturating_sub(line.len() as u16)) / 2;
-            for (j, c) in line.chars().enumerate() {
-                let tx = text_start_x + j as u16;
-                self.set(tx, ty, c);
-            }
+            let ty = start_y + i as i32;
+            let text_start_x = node.x + ((available_width.saturating_sub(display_width(line))) / 2) as i32;
+            self.put_text(text_start_x, ty, line, node.x + node.width as i32);
         }
         
+        // Brackets just outside the label, tucked inside it where the canvas edge leaves no room
         if node.selected {
-            self.set(node.x.saturating_sub(1), node.y, '[');
-            self.set(node.x + node.width, node.y + node.height - 1, ']');
+            let bottom = node.y + node.height.saturating_sub(1) as i32;
+            let left = if self.cell(node.x - 1, node.y).is_some() { node.x - 1 } else { node.x };
+            let right_edge = node.x + node.width as i32;
+            let right = if self.cell(right_edge, bottom).is_some() { right_edge } else { right_edge - 1 };
+            self.set(left, node.y, '[');
+            self.set(right, bottom, ']');
         }
     }
 
     pub fn draw_box(&mut self, node: &Node) {
         let x1 = node.x;
         let y1 = node.y;
-        let x2 = x1 + node.width - 1;
-        let y2 = y1 + node.height - 1;
+        let x2 = x1 + node.width as i32 - 1;
+        let y2 = y1 + node.height as i32 - 1;
 
-        let corner = if node.selected { '#' } else { '+' };
-        let horiz = if node.selected { '=' } else { '-' };
-        let vert = if node.selected { '#' } else { '|' };
+        // Shadow first, and only into blank cells so neighbours keep their outlines
+        if node.shadow {
+            for y in (y1 + 1)..=(y2 + 1) { self.set_if_blank(x2 + 1, y, ':'); }
+            for x in (x1 + 1)..=(x2 + 1) { self.set_if_blank(x, y2 + 1, ':'); }
+        }
+
+        let (top_corner, bottom_corner, horiz, vert) = border_glyphs(node);
 
         // Corners
-        self.set(x1, y1, corner);
-        self.set(x2, y1, corner);
-        self.set(x1, y2, corner);
-        self.set(x2, y2, corner);
+        self.set(x1, y1, top_corner);
+        self.set(x2, y1, top_corner);
+        self.set(x1, y2, bottom_corner);
+        self.set(x2, y2, bottom_corner);
 
         // Horizontal lines
         for x in (x1 + 1)..x2 {
@@ -79,38 +184,51 @@ impl Canvas {
             let total_lines = lines.len() as u16;
             
             // Start Y to center vertically
-            let start_y = y1 + 1 + (available_height.saturating_sub(total_lines)) / 2;
+            let start_y = y1 + 1 + ((available_height.saturating_sub(total_lines)) / 2) as i32;
             
             for (i, line) in lines.iter().enumerate().take(available_height as usize) {
-                let ty = start_y + i as u16;
+                let ty = start_y + i as i32;
                 if ty > y1 && ty < y2 {
-                    let text_start_x = x1 + 1 + (available_width.saturating_sub(line.len() as u16)) / 2;
-                    for (j, c) in line.chars().enumerate() {
-                        let tx = text_start_x + j as u16;
-                        if tx > x1 && tx < x2 {
-                            self.set(tx, ty, c);
-                        }
-                    }
+                    let text_start_x = x1 + 1 + ((available_width.saturating_sub(display_width(line))) / 2) as i32;
+                    self.put_text(text_start_x, ty, line, x2);
                 }
             }
         }
     }
 
+    /// Small id tag on a shape: on the top border of boxes and frames, in the empty top-left
+    /// corner of a diamond's bounding box, and just left of a text node, so it never covers the label.
+    pub fn draw_badge(&mut self, node: &Node) {
+        let badge = node.id.to_string();
+        match node.shape {
+            ShapeType::Box | ShapeType::Frame => {
+                let limit = node.x + node.width.saturating_sub(1) as i32;
+                self.put_text(node.x + 1, node.y, &badge, limit);
+            }
+            ShapeType::Diamond => {
+                let limit = node.x + (node.width / 2) as i32;
+                self.put_text(node.x, node.y, &badge, limit);
+            }
+            ShapeType::Text => {
+                let x = node.x - display_width(&badge) as i32 - 1;
+                self.put_text(x, node.y, &badge, node.x);
+            }
+        }
+    }
+
     pub fn draw_frame(&mut self, node: &Node) {
         let x1 = node.x;
         let y1 = node.y;
-        let x2 = x1 + node.width - 1;
-        let y2 = y1 + node.height - 1;
+        let x2 = x1 + node.width as i32 - 1;
+        let y2 = y1 + node.height as i32 - 1;
 
-        let corner = if node.selected { '#' } else { '+' };
-        let horiz = if node.selected { '=' } else { '-' };
-        let vert = if node.selected { '#' } else { '|' };
+        let (top_corner, bottom_corner, horiz, vert) = border_glyphs(node);
 
         // Corners
-        self.set(x1, y1, corner);
-        self.set(x2, y1, corner);
-        self.set(x1, y2, corner);
-        self.set(x2, y2, corner);
+        self.set(x1, y1, top_corner);
+        self.set(x2, y1, top_corner);
+        self.set(x1, y2, bottom_corner);
+        self.set(x2, y2, bottom_corner);
 
         // Horizontal lines
         for x in (x1 + 1)..x2 {
@@ -124,37 +242,20 @@ impl Canvas {
             self.set(x2, y, vert);
         }
 
-        // Draw title if present
-        if !node.text.is_empty() {
-            let title = format!(" [ {} ] ", node.text);
-            let title_len = title.len() as u16;
-            let available_width = node.width.saturating_sub(4);
-            
-            if title_len <= available_width {
-                let tx = x1 + 2;
-                for (i, c) in title.chars().enumerate() {
-                    self.set(tx + i as u16, y1, c);
-                }
-            } else if available_width > 10 {
-                // Truncate title
-                let truncated = format!(" [ {}... ] ", &node.text[..available_width.saturating_sub(7) as usize]);
-                let tx = x1 + 2;
-                for (i, c) in truncated.chars().enumerate() {
-                    self.set(tx + i as u16, y1, c);
-                }
-            }
+        // The whole label is the title, left-aligned in the top border
+        if let Some(title) = frame_title(node) {
+            self.put_text(x1 + 2, y1, &title, x2);
         }
     }
 
     pub fn draw_diamond(&mut self, node: &Node) {
         let x1 = node.x;
         let y1 = node.y;
-        let x2 = node.x + node.width - 1;
-        let y2 = node.y + node.height - 1;
-        let cx = x1 + node.width / 2;
-        let cy = y1 + node.height / 2;
+        let x2 = node.x + node.width as i32 - 1;
+        let y2 = node.y + node.height as i32 - 1;
+        let (cx, cy) = node.center();
 
-        let point = if node.selected { '#' } else { '+' };
+        let point = if node.locked { '*' } else if node.selected { '#' } else { '+' };
 
         // Top to Right
         self.draw_line(cx, y1, x2, cy, if node.selected { '#' } else { '/' });
@@ -171,44 +272,28 @@ impl Canvas {
         self.set(x1, cy, point);
         self.set(x2, cy, point);
 
-        // Text wrapping for Diamond
-        let available_width = node.width.saturating_sub(6).max(1);
-        let available_height = node.height.saturating_sub(2).max(1);
-        
-        let lines = crate::model::wrap_text(&node.text, available_width);
-        let total_lines = lines.len() as u16;
-        
-        let start_y = y1 + 1 + (available_height.saturating_sub(total_lines)) / 2;
-        
-        for (i, line) in lines.iter().enumerate().take(available_height as usize) {
-            let ty = start_y + i as u16;
-            let text_start_x = x1 + (node.width.saturating_sub(line.len() as u16)) / 2;
-            
-            for (j, c) in line.chars().enumerate() {
-                let tx = text_start_x + j as u16;
-                if ty > y1 && ty < y2 && tx > x1 + 1 && tx < x2 - 1 {
-                    self.set(tx, ty, c);
-                }
-            }
+        for (tx, ty, line) in diamond_text_layout(node) {
+            let (start, width) = diamond_row_span(node, ty);
+            self.put_text(tx, ty, &line, start + width as i32);
         }
     }
 
-    fn draw_line(&mut self, x1: u16, y1: u16, x2: u16, y2: u16, c: char) {
-        let dx = (x2 as i32 - x1 as i32).abs();
-        let dy = (y2 as i32 - y1 as i32).abs();
+    fn draw_line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, c: char) {
+        let dx = (x2 - x1).abs();
+        let dy = (y2 - y1).abs();
         let sx = if x1 < x2 { 1 } else { -1 };
         let sy = if y1 < y2 { 1 } else { -1 };
         let mut err = dx - dy;
 
-        let mut x = x1 as i32;
-        let mut y = y1 as i32;
+        let mut x = x1;
+        let mut y = y1;
 
         loop {
-            if (x as u16 != x1 || y as u16 != y1) && (x as u16 != x2 || y as u16 != y2) {
-                self.set(x as u16, y as u16, c);
+            if (x != x1 || y != y1) && (x != x2 || y != y2) {
+                self.set(x, y, c);
             }
 
-            if x == x2 as i32 && y == y2 as i32 {
+            if x == x2 && y == y2 {
                 break;
             }
             let e2 = 2 * err;
@@ -223,190 +308,640 @@ impl Canvas {
         }
     }
 
+    /// Draws a connection. Its arrowheads normally sit in the gap just outside the shape they
+    /// point at; when that cell is off the canvas they go on the shape's border instead.
+    ///
+    /// ```
+    /// use dxgmr::builder::DiagramBuilder;
+    /// use dxgmr::model::AppState;
+    /// use dxgmr::renderer::render_region;
+    ///
+    /// // Scrolled so the target's top border is the canvas's first row
+    /// let diagram = DiagramBuilder::new("Edge")
+    ///     .box_at(0, 0, "Above")
+    ///     .box_at(0, 10, "Below")
+    ///     .connect(1, 2).arrow()
+    ///     .build();
+    /// let canvas = render_region(&AppState::from_diagram(diagram), (0, 10), 20, 5);
+    /// assert_eq!(canvas.grid[0][10], 'v');
+    /// assert_eq!(canvas.grid[2].iter().collect::<String>().trim(), "|      Below       |");
+    /// ```
     pub fn draw_connection(&mut self, state: &AppState, index: usize) {
         let conn = &state.connections[index];
-        let from = state.nodes.iter().find(|n| n.id == conn.from_id);
-        let to = state.nodes.iter().find(|n| n.id == conn.to_id);
-
-        if let (Some(f), Some(t)) = (from, to) {
-            let x1 = f.x + conn.from_offset.0;
-            let y1 = f.y + conn.from_offset.1;
-            let mut x2 = t.x + conn.to_offset.0;
-            let mut y2 = t.y + conn.to_offset.1;
-
-            // Offset the arrowhead so it sits just outside the node border
-            if conn.has_arrow {
-                if conn.to_offset.1 == 0 {
-                    y2 = y2.saturating_sub(1);
-                } else if conn.to_offset.1 == t.height - 1 {
-                    y2 += 1;
-                } else if conn.to_offset.0 == 0 {
-                    x2 = x2.saturating_sub(1);
-                } else if conn.to_offset.0 == t.width - 1 {
-                    x2 += 1;
-                }
+        if let Some(mut segments) = conn.route(&state.nodes) {
+            if conn.has_arrow && conn.to.node_id().is_some()
+                && let Some(last) = segments.last_mut() {
+                last.1 = self.onto_border(last.0, last.1);
+            }
+            if conn.arrow_at_start() && let Some(first) = segments.first_mut() {
+                first.0 = self.onto_border(first.1, first.0);
             }
+            // Edges touching a selected node light up too, to trace flow through hubs
+            let is_selected = state.selected_connection_index == Some(index)
+                || state.nodes.iter().any(|n| n.selected && conn.touches(n.id));
+            self.draw_route(&segments, &state.glyphs, conn.arrow_at_start(), conn.has_arrow, conn.weight, is_selected);
+        }
+    }
 
-            let vertical_first = conn.from_offset.1 == 0 || conn.from_offset.1 == f.height - 1;
-            let is_selected = state.selected_connection_index == Some(index);
-            self.draw_route(x1, y1, x2, y2, conn.has_arrow, is_selected, vertical_first);
+    /// Writes a connection's label over its route, blanking a cell either side so the line
+    /// stops short of the text instead of running into it.
+    pub fn draw_connection_label(&mut self, state: &AppState, index: usize) {
+        let conn = &state.connections[index];
+        if conn.label.is_empty() { return; }
+        let width = display_width(&conn.label);
+        if let Some(segments) = conn.route(&state.nodes)
+            && let Some((x, y)) = label_spot(&segments, width) {
+            self.set_conn(x, y, ' ');
+            self.put_text(x + 1, y, &conn.label, i32::MAX);
+            self.set_conn(x + 1 + width as i32, y, ' ');
         }
     }
 
-    pub fn draw_partial_connection(&mut self, from_node: &Node, offset: (u16, u16), target: (u16, u16)) {
-        let x1 = from_node.x + offset.0;
-        let y1 = from_node.y + offset.1;
-        let x2 = target.0;
-        let y2 = target.1;
+    /// `end`, one step further along the leg from `from` when it's off the canvas but that next
+    /// cell isn't: an arrowhead nudged past a border falls back onto the border.
+    fn onto_border(&self, from: Point, end: Point) -> Point {
+        if self.cell(end.0, end.1).is_some() || from == end { return end; }
+        let step = |a: i32, b: i32| b + (b - a).signum();
+        let border = (step(from.0, end.0), step(from.1, end.1));
+        if self.cell(border.0, border.1).is_some() { border } else { end }
+    }
 
-        let vertical_first = offset.1 == 0 || offset.1 == from_node.height - 1;
-        self.draw_route(x1, y1, x2, y2, true, true, vertical_first); // Active partial is highlighted
+    pub fn draw_partial_connection(&mut self, glyphs: &GlyphSet, from_node: &Node, offset: (u16, u16), target: Point) {
+        let offset = from_node.clamp_offset(offset);
+        let start = from_node.point_at(offset);
+        let vertical_first = offset.1 == 0 || offset.1 == from_node.height.saturating_sub(1);
+        let segments = route_segments(start, target, vertical_first, vertical_first, Bend::Middle);
+        self.draw_route(&segments, glyphs, false, true, Weight::Thin, true); // Active partial is highlighted
     }
 
-    fn draw_route(&mut self, x1: u16, y1: u16, x2: u16, y2: u16, arrow: bool, highlighted: bool, vertical_first: bool) {
-        let horiz = if highlighted { '=' } else { '-' };
-        let vert = if highlighted { '#' } else { '|' };
-        let join = if highlighted { '#' } else { '+' };
-        let start = if highlighted { '@' } else { 'o' };
+    fn draw_route(&mut self, segments: &[Segment], glyphs: &GlyphSet, start_arrow: bool, arrow: bool, weight: Weight, highlighted: bool) {
+        let line = glyphs.line(weight, highlighted);
+        let (horiz, vert, join) = (line.horizontal, line.vertical, line.join);
+        let start = if highlighted { glyphs.start_highlighted } else { glyphs.start };
 
-        let mid_y = (y1 + y2) / 2;
-        let mid_x = (x1 + x2) / 2;
+        let (Some(&(first, _)), Some(&(_, last))) = (segments.first(), segments.last()) else { return };
 
-        if vertical_first {
-            // Vertical -> Horizontal -> Vertical (Z-route)
-            
-            // First vertical segment
-            let iy1 = y1.min(mid_y);
-            let iy2 = y1.max(mid_y);
-            for y in iy1..=iy2 { self.set_conn(x1, y, vert); }
-            
-            // Horizontal segment
-            let ix1 = x1.min(x2);
-            let ix2 = x1.max(x2);
-            for x in ix1..=ix2 { self.set_conn(x, mid_y, horiz); }
-            
-            // Second vertical segment
-            let iy3 = mid_y.min(y2);
-            let iy4 = mid_y.max(y2);
-            for y in iy3..=iy4 { self.set_conn(x2, y, vert); }
-            
-            // Corners
-            if x1 != x2 {
-                self.set_conn(x1, mid_y, join);
-                self.set_conn(x2, mid_y, join);
+        // Zero-length segments have no direction; drawing them would fake a crossing
+        let drawn: Vec<&Segment> = segments.iter().filter(|(a, b)| a != b).collect();
+        for &&((x1, y1), (x2, y2)) in &drawn {
+            if x1 == x2 {
+                for y in y1.min(y2)..=y1.max(y2) { self.set_stroke(x1, y, Stroke::Vertical, vert, join); }
+            } else {
+                for x in x1.min(x2)..=x1.max(x2) { self.set_stroke(x, y1, Stroke::Horizontal, horiz, join); }
             }
-        } else {
-            // Horizontal -> Vertical -> Horizontal (S-route)
-            
-            // First horizontal segment
-            let ix1 = x1.min(mid_x);
-            let ix2 = x1.max(mid_x);
-            for x in ix1..=ix2 { self.set_conn(x, y1, horiz); }
-            
-            // Vertical segment
-            let iy1 = y1.min(y2);
-            let iy2 = y1.max(y2);
-            for y in iy1..=iy2 { self.set_conn(mid_x, y, vert); }
-            
-            // Second horizontal segment
-            let ix3 = mid_x.min(x2);
-            let ix4 = mid_x.max(x2);
-            for x in ix3..=ix4 { self.set_conn(x, y2, horiz); }
-            
-            // Corners
-            if y1 != y2 {
-                self.set_conn(mid_x, y1, join);
-                self.set_conn(mid_x, y2, join);
+        }
+
+        // Corners where the route actually turns
+        for pair in drawn.windows(2) {
+            let turns = (pair[0].0.0 == pair[0].1.0) != (pair[1].0.0 == pair[1].1.0);
+            if turns {
+                self.set_conn(pair[0].1.0, pair[0].1.1, join);
             }
         }
         
         // Re-render start
-        self.set_conn(x1, y1, start);
+        if start_arrow {
+            self.set_conn(first.0, first.1, tail_head(segments, glyphs));
+        } else {
+            self.set_conn(first.0, first.1, start);
+        }
         
         if arrow {
-            let arrow_char = if vertical_first {
-                if y2 != mid_y {
-                    if y1 < y2 { 'v' } else { '^' }
-                } else {
-                    if x1 < x2 { '>' } else { '<' }
-                }
-            } else {
-                if x2 != mid_x {
-                    if x1 < x2 { '>' } else { '<' }
-                } else {
-                    if y1 < y2 { 'v' } else { '^' }
-                }
-            };
-            self.set_conn(x2, y2, arrow_char);
+            self.set_conn(last.0, last.1, arrow_head(segments, glyphs));
         } else {
-            self.set_conn(x2, y2, start);
+            self.set_conn(last.0, last.1, start);
+        }
+    }
+
+    /// Dotted selection rectangle between two screen-space corners (may lie partly off-canvas).
+    pub fn draw_rubber_band(&mut self, a: (i32, i32), b: (i32, i32)) {
+        let (x1, x2) = (a.0.min(b.0), a.0.max(b.0));
+        let (y1, y2) = (a.1.min(b.1), a.1.max(b.1));
+        let mut put = |x: i32, y: i32, c: char| self.set(x, y, c);
+        for x in x1..=x2 {
+            put(x, y1, '.');
+            put(x, y2, '.');
+        }
+        for y in y1..=y2 {
+            put(x1, y, ':');
+            put(x2, y, ':');
+        }
+    }
+
+    /// The legend's box in `corner` of the `width` x `height` area whose top-left is `origin`,
+    /// covering whatever is drawn there. Left out when the area is too small to hold it.
+    pub fn draw_legend(&mut self, legend: &Legend, corner: Corner, origin: Point, width: u16, height: u16) {
+        let Some((w, h)) = legend.size() else { return };
+        if w > width || h > height { return; }
+        let x1 = match corner {
+            Corner::TopLeft | Corner::BottomLeft => origin.0,
+            Corner::TopRight | Corner::BottomRight => origin.0 + (width - w) as i32,
+        };
+        let y1 = match corner {
+            Corner::TopLeft | Corner::TopRight => origin.1,
+            Corner::BottomLeft | Corner::BottomRight => origin.1 + (height - h) as i32,
+        };
+        let (x2, y2) = (x1 + w as i32 - 1, y1 + h as i32 - 1);
+        for y in y1..=y2 {
+            for x in x1..=x2 {
+                let c = match (x == x1 || x == x2, y == y1 || y == y2) {
+                    (true, true) => '+',
+                    (false, true) => '-',
+                    (true, false) => '|',
+                    (false, false) => ' ',
+                };
+                self.set_conn(x, y, c);
+            }
+        }
+        self.put_text(x1 + 1, y1, LEGEND_HEADING, x2);
+        for (i, entry) in legend.entries.iter().enumerate() {
+            self.put_text(x1 + 2, y1 + 1 + i as i32, entry, x2);
+        }
+    }
+
+    /// Scaled-down overview of the whole diagram in the bottom-right corner: `█` for shapes,
+    /// `░` for the part of the diagram currently on screen. Nothing is drawn when the canvas is
+    /// too small to fit a useful map.
+    pub fn draw_minimap(&mut self, state: &AppState) {
+        let Some((bx, by, bw, bh)) = state.bounds() else { return };
+        let map_w = (self.width / 3).min(MINIMAP_WIDTH);
+        let map_h = (self.height / 3).min(MINIMAP_HEIGHT);
+        if map_w < 4 || map_h < 2 { return; }
+
+        // World area covered: the diagram plus wherever the viewport has wandered off to
+        let (cam_x, cam_y) = state.camera_offset;
+        let x0 = bx.min(cam_x);
+        let y0 = by.min(cam_y);
+        let x1 = (bx + bw as i32).max(cam_x + self.width as i32);
+        let y1 = (by + bh as i32).max(cam_y + self.height as i32);
+        let col = |wx: i32| ((wx - x0) as i64 * map_w as i64 / (x1 - x0) as i64).clamp(0, map_w as i64 - 1) as i32;
+        let row = |wy: i32| ((wy - y0) as i64 * map_h as i64 / (y1 - y0) as i64).clamp(0, map_h as i64 - 1) as i32;
+
+        let left = (self.width - map_w - 3) as i32;
+        let top = (self.height - map_h - 3) as i32;
+        let (map_w, map_h) = (map_w as i32, map_h as i32);
+        for y in top..top + map_h + 2 {
+            for x in left..left + map_w + 2 {
+                let edge_x = x == left || x == left + map_w + 1;
+                let edge_y = y == top || y == top + map_h + 1;
+                let c = match (edge_x, edge_y) {
+                    (true, true) => '+',
+                    (false, true) => '-',
+                    (true, false) => '|',
+                    (false, false) => ' ',
+                };
+                self.set_conn(x, y, c);
+            }
+        }
+
+        let (view_w, view_h) = (self.width as i32, self.height as i32);
+        let mut fill = |wx1: i32, wy1: i32, wx2: i32, wy2: i32, c: char| {
+            for y in row(wy1)..=row(wy2) {
+                for x in col(wx1)..=col(wx2) {
+                    self.set(left + 1 + x, top + 1 + y, c);
+                }
+            }
+        };
+        fill(cam_x, cam_y, cam_x + view_w - 1, cam_y + view_h - 1, '░');
+        for n in &state.nodes {
+            fill(n.x, n.y, n.x + n.width as i32 - 1, n.y + n.height as i32 - 1, '█');
         }
     }
 
     // Special set that doesn't overwrite node boundaries or text if we want,
-    fn set_conn(&mut self, x: u16, y: u16, c: char) {
+    fn set_conn(&mut self, x: i32, y: i32, c: char) {
         self.set(x, y, c);
+        if let Some((row, col)) = self.cell(x, y) {
+            self.strokes[row][col] = Stroke::None;
+        }
     }
 
-    pub fn to_string(&self) -> String {
-        let mut out = String::new();
+    /// Draws one cell of a straight line. Where it crosses another connection running the
+    /// other way, the cell gets `cross` instead so neither line appears broken.
+    fn set_stroke(&mut self, x: i32, y: i32, stroke: Stroke, c: char, cross: char) {
+        let Some((row, col)) = self.cell(x, y) else { return };
+        let merged = match self.strokes[row][col] {
+            Stroke::None => stroke,
+            existing if existing == stroke => stroke,
+            _ => Stroke::Both,
+        };
+        self.set(x, y, if merged == Stroke::Both { cross } else { c });
+        self.strokes[row][col] = merged;
+    }
+
+    /// Writes every row as `Display` shows it, padded to the full width, one glyph at a time.
+    pub fn render<W: std::fmt::Write>(&self, w: &mut W) -> std::fmt::Result {
+        for row in &self.grid {
+            for &c in row.iter().filter(|&&c| c != WIDE_TAIL) { w.write_char(c)?; }
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// `render` to a byte stream such as a file or socket, as UTF-8.
+    ///
+    /// ```
+    /// use dxgmr::model::{AppState, ShapeType};
+    /// use dxgmr::renderer::render_to_canvas;
+    ///
+    /// let mut state = AppState::new(String::from("Streamed"));
+    /// state.add_node(ShapeType::Diamond, 2, 1, "Ok?");
+    /// let canvas = render_to_canvas(&state, 20, 9);
+    ///
+    /// let mut out = Vec::new();
+    /// canvas.render_io(&mut out).unwrap();
+    /// assert_eq!(String::from_utf8(out).unwrap(), canvas.to_string());
+    /// ```
+    pub fn render_io<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = [0; 4];
         for row in &self.grid {
-            let line: String = row.iter().collect();
-            out.push_str(&line);
-            out.push('\n');
+            for &c in row.iter().filter(|&&c| c != WIDE_TAIL) { w.write_all(c.encode_utf8(&mut buf).as_bytes())?; }
+            w.write_all(b"\n")?;
         }
-        out
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Canvas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.render(f)
+    }
+}
+
+/// Border glyphs `(top corners, bottom corners, horizontal, vertical)` for a box or frame.
+/// Selection and locking override the style so their state is always visible.
+fn border_glyphs(node: &Node) -> (char, char, char, char) {
+    let (top, bottom, horiz, vert) = match node.style {
+        _ if node.selected => ('#', '#', '=', '#'),
+        NodeStyle::Plain => ('+', '+', '-', '|'),
+        NodeStyle::Rounded => ('.', '\'', '-', '|'),
+        NodeStyle::Double => ('#', '#', '=', 'H'),
+    };
+    if node.locked { ('*', '*', horiz, vert) } else { (top, bottom, horiz, vert) }
+}
+
+/// Arrowhead for a route: points the way the final non-degenerate segment travels into the
+/// endpoint, whichever side of the target that is. A route with no length falls back to the right-pointing glyph.
+fn arrow_head(segments: &[Segment], glyphs: &GlyphSet) -> char {
+    match segments.iter().rev().find(|(a, b)| a != b) {
+        Some(&((sx, sy), (ex, ey))) if sx == ex => if ey > sy { glyphs.arrow_down } else { glyphs.arrow_up },
+        Some(&((sx, _), (ex, _))) => if ex > sx { glyphs.arrow_right } else { glyphs.arrow_left },
+        None => glyphs.arrow_right,
+    }
+}
+
+/// Arrowhead for the start of a route, pointing back along its first leg at the source.
+fn tail_head(segments: &[Segment], glyphs: &GlyphSet) -> char {
+    match segments.iter().find(|(a, b)| a != b) {
+        Some(&((sx, sy), (ex, ey))) if sx == ex => if ey > sy { glyphs.arrow_up } else { glyphs.arrow_down },
+        Some(&((sx, _), (ex, _))) => if ex > sx { glyphs.arrow_left } else { glyphs.arrow_right },
+        None => glyphs.arrow_left,
     }
 }
 
+/// Usable text span `(start_x, width)` on row `ty` of a diamond, one cell clear of the outline.
+fn diamond_row_span(node: &Node, ty: i32) -> (i32, u16) {
+    let (cx, cy) = node.center();
+    let half_h = (node.height / 2).max(1);
+    let dy = (ty.abs_diff(cy).min(half_h as u32)) as u16;
+    // Distance from the center to the inner side of the outline on this row
+    let reach = ((2 * (half_h - dy)).saturating_sub(1) * (node.width / 2)) / (2 * half_h);
+    if reach < 2 {
+        return (cx, 0);
+    }
+    let half = reach - 1;
+    (cx - half as i32, half * 2 + 1)
+}
+
+/// A frame's label as it sits in the top border, `" [ title ] "`, cut short with `…` when the
+/// frame is too narrow. `None` when there's no title or no room for even one character.
+pub fn frame_title(node: &Node) -> Option<String> {
+    let text = node.text.split('\n').collect::<Vec<_>>().join(" ");
+    if text.is_empty() { return None; }
+    let title = format!(" [ {} ] ", text);
+    let available_width = node.width.saturating_sub(4);
+    if display_width(&title) <= available_width {
+        Some(title)
+    } else if available_width > 7 {
+        let kept = crate::model::truncate_to_width(&text, available_width - 7);
+        Some(format!(" [ {}… ] ", kept))
+    } else {
+        None
+    }
+}
+
+/// Lays out a diamond's label as `(x, y, line)` triples: the label is wrapped so each line fits
+/// the diamond's width on the row it lands on, narrow at the tips and widest in the middle.
+pub fn diamond_text_layout(node: &Node) -> Vec<(i32, i32, String)> {
+    if node.height < 3 { return Vec::new(); }
+    let cy = node.center().1;
+    let rows: Vec<i32> = ((node.y + 1)..(node.y + node.height as i32 - 1))
+        .filter(|&ty| diamond_row_span(node, ty).1 > 0)
+        .collect();
+    if rows.is_empty() { return Vec::new(); }
+
+    // Try the fewest centered rows that hold the whole label without splitting words, falling
+    // back to the tallest layout
+    let words = node.text.split_whitespace().count();
+    let mut best = Vec::new();
+    for count in 1..=rows.len() {
+        let first = (cy - (count as i32 - 1) / 2).max(rows[0]);
+        let first = first.min(rows[rows.len() - count]);
+        let used: Vec<i32> = (first..first + count as i32).collect();
+        let widths: Vec<u16> = used.iter().map(|&ty| diamond_row_span(node, ty).1).collect();
+        let lines = crate::model::wrap_text_varying(&node.text, &widths, false);
+        let fits = lines.len() <= count && lines.iter().map(|l| l.split_whitespace().count()).sum::<usize>() == words;
+        best = used.into_iter().zip(lines).map(|(ty, line)| {
+            let (start, width) = diamond_row_span(node, ty);
+            let tx = start + (width.saturating_sub(display_width(&line)) / 2) as i32;
+            (tx, ty, line)
+        }).collect();
+        if fits { break; }
+    }
+    best
+}
+
 pub fn render_to_canvas(state: &AppState, width: u16, height: u16) -> Canvas {
+    render_region(state, state.camera_offset, width, height)
+}
+
+/// Writes the diagram as text for saving or copying, from `AppState::export_origin` rather than
+/// the camera, with trailing blank rows dropped. It is rendered a band of `EXPORT_BAND_ROWS` rows
+/// at a time, so exporting a huge diagram never holds more than one band's grid in memory.
+pub fn write_ascii<W: Write>(state: &AppState, width: u16, height: u16, writer: &mut W) -> io::Result<()> {
+    // Blank rows are held back until something follows them, as trailing ones are dropped
+    let mut pending_blank = 0;
+    let start = state.export_origin();
+    for top in (0..height).step_by(EXPORT_BAND_ROWS as usize) {
+        let rows = EXPORT_BAND_ROWS.min(height - top);
+        let origin = (start.0, start.1 + top as i32);
+        let legend_area = (state.legend.corner.lowered(), start, width, height);
+        let band = render_area(state, origin, width, rows, legend_area);
+        for line in band.export_lines() {
+            if line.is_empty() {
+                pending_blank += 1;
+                continue;
+            }
+            for _ in 0..pending_blank { writeln!(writer)?; }
+            pending_blank = 0;
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders the `width` x `height` cells whose top-left is at world coordinate `origin`.
+///
+/// ```
+/// use dxgmr::model::{AppState, Corner, ShapeType};
+/// use dxgmr::renderer::render_region;
+///
+/// let mut state = AppState::new(String::from("Key"));
+/// state.add_node(ShapeType::Box, 0, 0, "Step");
+/// state.legend.entries = vec![String::from("Box: a step")];
+/// state.legend.visible = true;
+/// state.legend.corner = Corner::BottomRight;
+/// let canvas = render_region(&state, (0, 0), 40, 10);
+/// let rows: Vec<String> = canvas.grid.iter().map(|r| r.iter().collect()).collect();
+/// assert_eq!(&rows[7][25..], "+ Legend -----+");
+/// assert_eq!(&rows[8][25..], "| Box: a step |");
+///
+/// // Not a node: a click on it finds nothing to select
+/// assert!(state.node_at(30, 8).is_none());
+/// ```
+pub fn render_region(state: &AppState, origin: (i32, i32), width: u16, height: u16) -> Canvas {
+    render_area(state, origin, width, height, (state.legend.corner, origin, width, height))
+}
+
+/// `render_region`, with the legend placed in a corner of `legend_area` (corner, origin, width,
+/// height) rather than of this region: an export drawn in bands keeps a single legend.
+fn render_area(state: &AppState, origin: (i32, i32), width: u16, height: u16, legend_area: (Corner, Point, u16, u16)) -> Canvas {
     let mut canvas = Canvas::new(width, height);
-    
-    // Create temp state with camera-adjusted positions
-    let mut nodes = Vec::new();
-    for n in &state.nodes {
-        let mut node = n.clone();
-        node.x = (n.x as i32 - state.camera_offset.0).max(0) as u16;
-        node.y = (n.y as i32 - state.camera_offset.1).max(0) as u16;
-        nodes.push(node);
-    }
-
-    let mut temp_state = AppState::new(state.title.clone());
-    temp_state.nodes = nodes;
-    temp_state.connections = state.connections.clone();
-    temp_state.selected_connection_index = state.selected_connection_index;
-    
-    if let Some(crate::model::PartialConnection::Starting { from_id, from_offset, current_pos }) = &state.partial_connection {
-        temp_state.partial_connection = Some(crate::model::PartialConnection::Starting {
-            from_id: *from_id,
-            from_offset: *from_offset,
-            current_pos: (
-                (current_pos.0 as i32 - state.camera_offset.0).max(0) as u16,
-                (current_pos.1 as i32 - state.camera_offset.1).max(0) as u16,
-            ),
-        });
+
+    // Alignment guides sit underneath everything else
+    if let Some(col) = state.snap_guides.0 {
+        for y in 0..height as i32 { canvas.set(col - origin.0, y, ':'); }
+    }
+    if let Some(row) = state.snap_guides.1 {
+        for x in 0..width as i32 { canvas.set(x, row - origin.1, '.'); }
+    }
+
+    // Shapes are drawn straight from the model in world coordinates; the canvas origin sits at
+    // the camera, clipping whatever falls before it, so shapes straddling the top or left edge are
+    // cut off rather than squashed against it.
+    canvas.origin = origin;
+
+    // Labels go on once every line is down, so no other route runs through their text
+    let draw_edges = |canvas: &mut Canvas| {
+        for i in 0..state.connections.len() {
+            canvas.draw_connection(state, i);
+        }
+        for i in 0..state.connections.len() {
+            canvas.draw_connection_label(state, i);
+        }
+    };
+
+    if state.edges_behind {
+        draw_edges(&mut canvas);
     }
 
     // Draw nodes
-    for node in &temp_state.nodes {
+    for node in &state.nodes {
+        if state.edges_behind {
+            canvas.clear_interior(node);
+        }
         match node.shape {
             ShapeType::Box => canvas.draw_box(node),
             ShapeType::Diamond => canvas.draw_diamond(node),
             ShapeType::Text => canvas.draw_text_node(node),
             ShapeType::Frame => canvas.draw_frame(node),
         }
+        if state.show_ids || matches!(state.mode, crate::model::AppMode::QuickConnect { .. }) {
+            canvas.draw_badge(node);
+        }
     }
 
     // Draw connections after nodes
-    for i in 0..temp_state.connections.len() {
-        canvas.draw_connection(&temp_state, i);
+    if !state.edges_behind {
+        draw_edges(&mut canvas);
     }
 
-    if let Some(crate::model::PartialConnection::Starting { from_id, from_offset, current_pos }) = &temp_state.partial_connection {
-        if let Some(node) = temp_state.nodes.iter().find(|n| n.id == *from_id) {
-            canvas.draw_partial_connection(node, *from_offset, *current_pos);
+    // Keyboard connect: preview the link Enter would create to the selected shape
+    if let Some(src_id) = state.connection_source_id
+        && state.mode == crate::model::AppMode::Normal
+        && let Some(src) = state.nodes.iter().find(|n| n.id == src_id)
+        && let Some(dst) = state.nodes.iter().find(|n| n.selected && n.id != src_id) {
+        let (from_offset, to_offset) = crate::model::facing_anchors(src, dst);
+        let (from_offset, to_offset) = state.link_anchors(src, from_offset, dst, to_offset);
+        let preview = Connection {
+            from_id: src_id,
+            from_offset,
+            to: Endpoint::NodeAnchor { to_id: dst.id, to_offset },
+            has_arrow: state.connection_has_arrow,
+            weight: Weight::Thin,
+            bend: Bend::Middle,
+            bidirectional: false,
+            label: String::new(),
+        };
+        if let Some(segments) = preview.route(&state.nodes) {
+            canvas.draw_route(&segments, &state.glyphs, false, preview.has_arrow, preview.weight, true);
         }
     }
 
+    if let Some(crate::model::PartialConnection::Starting { from_id, from_offset, current_pos }) = &state.partial_connection
+        && let Some(node) = state.nodes.iter().find(|n| n.id == *from_id) {
+        canvas.draw_partial_connection(&state.glyphs, node, *from_offset, *current_pos);
+    }
+
+    let (corner, legend_origin, legend_width, legend_height) = legend_area;
+    canvas.draw_legend(&state.legend, corner, legend_origin, legend_width, legend_height);
+
+    // Back to screen coordinates for overlays
+    canvas.origin = (0, 0);
+
+    if let Some((start, current)) = state.rubber_band {
+        canvas.draw_rubber_band(
+            (start.0 - origin.0, start.1 - origin.1),
+            (current.0 - origin.0, current.1 - origin.1),
+        );
+    }
+
     canvas
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DiagramBuilder;
+
+    #[test]
+    fn arrow_head_points_into_each_side() {
+        // The target sits at (30, 15); each source is off one of its sides
+        let cases = [("top", (30, 0), 'v'), ("bottom", (30, 30), '^'), ("left", (0, 15), '>'), ("right", (60, 15), '<')];
+        for (side, (x, y), expected) in cases {
+            let diagram = DiagramBuilder::new("Arrows").box_at(30, 15, "Target").box_at(x, y, "Source").connect(2, 1).arrow().build();
+            let state = AppState::from_diagram(diagram);
+            let route = state.connections[0].route(&state.nodes).unwrap();
+            assert_eq!(arrow_head(&route, &state.glyphs), expected, "{}", side);
+            let (ex, ey) = route.last().unwrap().1;
+            let canvas = render_region(&state, (0, 0), 90, 40);
+            assert_eq!(canvas.grid[ey as usize][ex as usize], expected, "{}", side);
+        }
+    }
+
+    /// Each row as printed, wide-glyph tails dropped.
+    fn rows(canvas: &Canvas) -> Vec<String> {
+        canvas.grid.iter().map(|row| row.iter().filter(|&&c| c != WIDE_TAIL).collect()).collect()
+    }
+
+    #[test]
+    fn offsets_outside_their_nodes_are_clamped() {
+        let diagram = DiagramBuilder::new("Clamp").box_at(0, 0, "A").box_at(40, 10, "B").box_at(0, 20, "C").connect(1, 2).arrow().build();
+        let mut state = AppState::from_diagram(diagram);
+        // As a hand-edited file might have them: past the right side of A and the top-right of B
+        state.connections[0].from_offset = (500, 2);
+        state.connections[0].to = Endpoint::NodeAnchor { to_id: 2, to_offset: (900, 0) };
+        let canvas = render_region(&state, (0, 0), 70, 30);
+        assert_eq!(canvas.grid[2][19], state.glyphs.start);
+        assert_eq!(canvas.grid[9][59], 'v');
+
+        // A shape with no size at all still takes links and draws without panicking
+        let node = state.nodes.iter_mut().find(|n| n.id == 3).unwrap();
+        (node.width, node.height) = (0, 0);
+        assert!(state.connect(1, 3, true));
+        assert!(state.connect(3, 2, false));
+        if let Endpoint::NodeAnchor { to_offset, .. } = &mut state.connections[1].to { *to_offset = (7, 7); }
+        state.connections[2].from_offset = (7, 7);
+        // Both ends land on its single cell at (0, 20); the arrowhead stops just above it
+        assert_eq!(state.connections[1].route(&state.nodes).unwrap().last().unwrap().1, (0, 19));
+        assert_eq!(state.connections[2].route(&state.nodes).unwrap()[0].0, (0, 20));
+        render_region(&state, (0, 0), 70, 30);
+    }
+
+    #[test]
+    fn wide_and_combining_labels_stay_inside_the_box() {
+        // Each label, and a piece of it that must show
+        let labels = [
+            ("Café déjà vu", "Café déjà vu"), // Precomposed accents
+            ("Cafe\u{301} de\u{301}ja\u{300} vu", "vu"), // Combining accents, the same width on screen
+            ("東京駅の案内所です", "東京駅の案内所です"), // Double-width, 18 columns
+            ("東京駅の案内所です 構内", "構内"), // Too wide for one line
+        ];
+        for (label, shown) in labels {
+            let state = AppState::from_diagram(DiagramBuilder::new("Wide").box_at(0, 0, label).build());
+            let box_width = state.nodes[0].width as usize;
+            let canvas = render_region(&state, (0, 0), 40, 8);
+            assert!(rows(&canvas).concat().contains(shown), "{:?}", label);
+            for (y, row) in rows(&canvas).iter().enumerate().take(state.nodes[0].height as usize) {
+                let row = row.trim_end();
+                assert_eq!(display_width(row) as usize, box_width, "{:?} row {}", label, y);
+                assert!(row.ends_with(['|', '+']), "{:?} row {}: {:?}", label, y, row);
+            }
+        }
+    }
+
+    #[test]
+    fn export_trims_trailing_spaces_and_blank_rows() {
+        let diagram = DiagramBuilder::new("Trim").box_at(0, 0, "東京").text_at(4, 8, "note").build();
+        let state = AppState::from_diagram(diagram);
+        let mut out = Vec::new();
+        write_ascii(&state, 60, 100, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        // The blank rows between the shapes stay; the 91 after the last one go
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[6], "");
+        assert_eq!(lines[8], "    note");
+        assert!(text.ends_with("note\n"));
+        for line in &lines {
+            assert_eq!(line.trim_end(), *line);
+            assert!(!line.contains(WIDE_TAIL));
+        }
+    }
+
+    #[test]
+    fn selected_text_brackets_fold_in_at_the_canvas_edge() {
+        let mut state = AppState::from_diagram(DiagramBuilder::new("Edge").text_at(0, 0, "note").build());
+        state.nodes[0].selected = true;
+        // With a cell free either side the brackets sit outside the label
+        assert_eq!(rows(&render_region(&state, (-1, 0), 6, 1))[0], "[note]");
+        // At the origin, with the canvas exactly as wide, they cover its first and last cells
+        assert_eq!(rows(&render_region(&state, (0, 0), 4, 1))[0], "[ot]");
+    }
+
+    #[test]
+    fn connection_label_clears_a_cell_either_side() {
+        let diagram = DiagramBuilder::new("Label").box_at(0, 0, "A").box_at(40, 0, "B").connect(1, 2).label("go").build();
+        let state = AppState::from_diagram(diagram);
+        let row = &rows(&render_region(&state, (0, 0), 60, 5))[2];
+        let at = row.find("go").unwrap();
+        assert_eq!(&row[at - 2..at + 4], "- go -");
+    }
+
+    #[test]
+    fn export_ignores_the_saved_camera() {
+        let diagram = DiagramBuilder::new("Scrolled").box_at(0, 0, "First").box_at(30, 10, "Second").connect(1, 2).arrow().build();
+        let mut state = AppState::from_diagram(diagram);
+        // Saved while scrolled past the first shape, then reopened
+        state.camera_offset = (25, 8);
+        let json = serde_json::to_string(&state.to_diagram()).unwrap();
+        let state = AppState::from_diagram(serde_json::from_str(&json).unwrap());
+        assert_eq!(state.camera_offset, (25, 8));
+
+        let mut out = Vec::new();
+        write_ascii(&state, state.export_columns(), state.export_rows(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 15);
+        assert_eq!(lines[0], "+------------------+");
+        assert!(lines[2].starts_with("|      First       o"));
+        assert!(lines[12].ends_with("|      Second      |"));
+    }
+
+    #[test]
+    fn box_keeps_runs_of_spaces() {
+        let state = AppState::from_diagram(DiagramBuilder::new("Spaces").box_at(0, 0, "a    b").build());
+        let canvas = render_region(&state, (0, 0), 20, 5);
+        assert_eq!(rows(&canvas)[2], "|      a    b      |");
+    }
+}